@@ -0,0 +1,155 @@
+//! Atom 1.0 feed of all posts, served as `/atom.xml` next to the RSS feed
+use crate::cache::feed_response;
+use crate::config::SiteConfig;
+use crate::post::Post;
+use crate::utils::{feed_title, feed_url, tag_path};
+use atom_syndication::{
+    CategoryBuilder, ContentBuilder, Entry, EntryBuilder, Feed, FeedBuilder, FixedDateTime,
+    LinkBuilder, PersonBuilder, Text,
+};
+use axum::extract::Path;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use chrono::NaiveDate;
+use tracing::instrument;
+
+/// Posts only have a date, so they count as published at midnight UTC
+fn timestamp(date: NaiveDate) -> FixedDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc().fixed_offset()
+}
+
+/// A `tag:` URI (RFC 4151) identifying a post independent of the url it is served under
+fn tag_uri(date: NaiveDate, path: &str) -> String {
+    let base_url = &SiteConfig::get().base_url;
+    let authority = base_url
+        .split_once("://")
+        .map_or(base_url.as_str(), |x| x.1);
+    let host = authority.split('/').next().unwrap_or(authority);
+    format!("tag:{host},{date}:{path}")
+}
+
+fn entry(post: &Post) -> Entry {
+    let config = SiteConfig::get();
+    let url = config.url(&post.path);
+    EntryBuilder::default()
+        .title(post.metadata.title.clone())
+        .id(tag_uri(post.metadata.date, &post.path))
+        .updated(timestamp(post.metadata.date))
+        .published(Some(timestamp(post.metadata.date)))
+        .link(
+            LinkBuilder::default()
+                .href(url.clone())
+                .rel("alternate".to_string())
+                .mime_type(Some("text/html".to_string()))
+                .build(),
+        )
+        .summary(Some(Text::plain(post.metadata.description.clone())))
+        .content(Some(
+            ContentBuilder::default()
+                .value(Some(post.content.clone()))
+                .base(Some(url))
+                .content_type(Some("html".to_string()))
+                .build(),
+        ))
+        .categories(
+            post.metadata
+                .tags
+                .iter()
+                .map(|tag| {
+                    CategoryBuilder::default()
+                        .term(tag.0.clone())
+                        .scheme(Some(tag.get_url()))
+                        .build()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .build()
+}
+
+/// Builds the feed from `posts`, which are expected newest first and tagged with `tag` if given
+#[instrument(skip(posts))]
+fn build_atom_feed(posts: &[&Post], tag: Option<&str>) -> Feed {
+    let config = SiteConfig::get();
+    let updated = posts
+        .iter()
+        .map(|post| post.metadata.date)
+        .max()
+        .map(timestamp)
+        .unwrap_or_default();
+    FeedBuilder::default()
+        .title(feed_title(tag))
+        .subtitle(Some(Text::plain(config.description.clone())))
+        .id(feed_url(tag, ""))
+        .updated(updated)
+        .author(
+            PersonBuilder::default()
+                .name(config.author.clone())
+                .uri(Some(config.base_url.clone()))
+                .build(),
+        )
+        .link(
+            LinkBuilder::default()
+                .href(feed_url(tag, "atom.xml"))
+                .rel("self".to_string())
+                .mime_type(Some("application/atom+xml".to_string()))
+                .build(),
+        )
+        .link(
+            LinkBuilder::default()
+                .href(match tag {
+                    Some(tag) => config.url(tag_path(tag)),
+                    None => config.url("/"),
+                })
+                .rel("alternate".to_string())
+                .mime_type(Some("text/html".to_string()))
+                .build(),
+        )
+        .lang(Some(config.language.clone()))
+        .entries(posts.iter().map(|post| entry(post)).collect::<Vec<_>>())
+        .build()
+}
+
+pub async fn serve_atom_feed(headers: HeaderMap) -> Response {
+    atom_response(None, &headers)
+}
+
+pub async fn serve_tag_atom_feed(Path(tag): Path<String>, headers: HeaderMap) -> Response {
+    atom_response(Some(&tag), &headers)
+}
+
+fn atom_response(tag: Option<&str>, headers: &HeaderMap) -> Response {
+    feed_response(
+        tag,
+        "atom.xml",
+        "application/atom+xml; charset=utf-8",
+        headers,
+        |posts| build_atom_feed(posts, tag).to_string().into_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_atom_feed() {
+        let post = Post::load("content/posts/The Basics.md".to_string())
+            .await
+            .unwrap();
+        let xml = build_atom_feed(&[&post], None).to_string();
+        let config = SiteConfig::get();
+
+        assert!(xml.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom""#));
+        assert!(xml.contains(&format!("<name>{}</name>", config.author)));
+        assert!(xml.contains(&format!(
+            "<id>tag:nereux.blog,{}:/posts/bitboard-rust</id>",
+            post.metadata.date
+        )));
+        assert!(xml.contains(&format!(
+            "<updated>{}T00:00:00+00:00</updated>",
+            post.metadata.date
+        )));
+        assert!(xml.contains(r#"type="html">&lt;"#));
+        assert!(xml.contains(r#"<category term="bitboard""#));
+    }
+}
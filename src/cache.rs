@@ -0,0 +1,310 @@
+//! HTTP caching: `Cache-Control` policies per route group and `ETag`/`Last-Modified` validators,
+//! so clients that already have the current version get a `304 Not Modified` instead of the body
+use crate::config::SiteConfig;
+use crate::post::Post;
+use crate::store::PostStore;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::Request;
+use axum::http::header::{
+    CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower_http::set_header::{MakeHeaderValue, SetResponseHeaderLayer};
+
+/// `Cache-Control` values per group of routes, set as `[cache]` in duckblog.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachePolicies {
+    /// Rendered html pages
+    #[serde(default = "default_pages")]
+    pub pages: String,
+    /// Feeds, the sitemap and robots.txt
+    #[serde(default = "default_feeds")]
+    pub feeds: String,
+    /// Files from `static/` and the content images
+    #[serde(default = "default_assets")]
+    pub assets: String,
+    /// Static files with a content hash in their name (`app.3f9a2c1b.css`)
+    #[serde(default = "default_fingerprinted")]
+    pub fingerprinted: String,
+}
+
+fn default_pages() -> String {
+    "public, max-age=300".to_string()
+}
+
+fn default_feeds() -> String {
+    "public, max-age=3600".to_string()
+}
+
+fn default_assets() -> String {
+    "public, max-age=86400".to_string()
+}
+
+fn default_fingerprinted() -> String {
+    "public, max-age=31536000, immutable".to_string()
+}
+
+impl Default for CachePolicies {
+    fn default() -> Self {
+        CachePolicies {
+            pages: default_pages(),
+            feeds: default_feeds(),
+            assets: default_assets(),
+            fingerprinted: default_fingerprinted(),
+        }
+    }
+}
+
+/// Sets `policy` as `Cache-Control` on the cacheable responses of the routes it is applied to
+pub fn cache_control(
+    policy: &str,
+) -> SetResponseHeaderLayer<impl MakeHeaderValue<Response> + Clone> {
+    let value = HeaderValue::from_str(policy)
+        .unwrap_or_else(|e| panic!("Invalid cache policy `{policy}`: {e}"));
+    SetResponseHeaderLayer::if_not_present(CACHE_CONTROL, move |response: &Response| {
+        is_cacheable(response.status()).then(|| value.clone())
+    })
+}
+
+/// Only successful and `304 Not Modified` responses get a policy, so errors, redirects and
+/// missing pages aren't kept by caches
+fn is_cacheable(status: StatusCode) -> bool {
+    status.is_success() || status == StatusCode::NOT_MODIFIED
+}
+
+/// Caches static files for [`CachePolicies::assets`], or forever if their name is fingerprinted
+pub async fn static_cache_control(request: Request, next: Next) -> Response {
+    let policies = &SiteConfig::get().cache;
+    let policy = if is_fingerprinted(request.uri().path()) {
+        &policies.fingerprinted
+    } else {
+        &policies.assets
+    };
+    let mut response = next.run(request).await;
+    if is_cacheable(response.status()) {
+        if let Ok(value) = HeaderValue::from_str(policy) {
+            response.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+    response
+}
+
+/// Whether a part of the file name between the name and the extension is a hash of 8 or more
+/// hex digits, like in `app.3f9a2c1b.css`
+fn is_fingerprinted(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let parts = file_name.split('.').collect::<Vec<_>>();
+    parts.len() > 2
+        && parts[1..parts.len() - 1]
+            .iter()
+            .any(|part| part.len() >= 8 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Adds a content hash `ETag` to successful responses and answers requests whose
+/// `If-None-Match` already has it with `304 Not Modified`
+pub async fn etag(request: Request, next: Next) -> Response {
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK || response.headers().contains_key(ETAG) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let etag = content_etag(&body);
+    if if_none_match.is_some_and(|etags| etag_matches(&etags, &etag)) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_TYPE);
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+        return Response::from_parts(parts, Body::empty());
+    }
+    parts
+        .headers
+        .insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Weak validator from the hash of `body`, stable across restarts. It is weak because the
+/// [`CompressionLayer`](tower_http::compression::CompressionLayer) sends the same body in
+/// different encodings, which must not share a strong `ETag`
+fn content_etag(body: &[u8]) -> String {
+    let hash = Sha256::digest(body);
+    format!(
+        "W/\"{}\"",
+        hash[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    )
+}
+
+/// Whether an `If-None-Match` header contains `etag`, using the weak comparison
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(etags) = if_none_match.to_str() else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    etags
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    body: Bytes,
+    content_type: &'static str,
+    /// Weak validator from the hash of the body
+    etag: String,
+    /// Truncated to seconds like the `Last-Modified` header
+    last_modified: SystemTime,
+}
+
+impl CachedResponse {
+    pub fn new(
+        body: impl Into<Bytes>,
+        content_type: &'static str,
+        last_modified: SystemTime,
+    ) -> Self {
+        let body = body.into();
+        let etag = content_etag(&body);
+        let seconds = last_modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        CachedResponse {
+            body,
+            content_type,
+            etag,
+            last_modified: UNIX_EPOCH + Duration::from_secs(seconds),
+        }
+    }
+
+    /// Whether the client's copy is still current. `If-None-Match` wins over `If-Modified-Since`.
+    fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        if let Some(etags) = headers.get(IF_NONE_MATCH) {
+            return etag_matches(etags, &self.etag);
+        }
+        headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|since| since.to_str().ok())
+            .and_then(|since| httpdate::parse_http_date(since).ok())
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    /// The full response, or `304 Not Modified` if the request's validators match
+    pub fn respond(&self, headers: &HeaderMap) -> Response {
+        let response = Response::builder()
+            .header(ETAG, &self.etag)
+            .header(LAST_MODIFIED, httpdate::fmt_http_date(self.last_modified));
+        if self.is_fresh(headers) {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap();
+        }
+        response
+            .header(CONTENT_TYPE, self.content_type)
+            .body(Body::from(self.body.clone()))
+            .unwrap()
+    }
+}
+
+/// Serves the feed `file` (`feed.xml`) of all posts or those tagged with `tag`. The feed is built
+/// by `build` once per version of the post store and answered from the cache afterwards.
+pub fn feed_response(
+    tag: Option<&str>,
+    file: &str,
+    content_type: &'static str,
+    headers: &HeaderMap,
+    build: impl FnOnce(&[&Post]) -> Vec<u8>,
+) -> Response {
+    let store = PostStore::current();
+    let render = || {
+        CachedResponse::new(
+            build(&store.newest_first(tag)),
+            content_type,
+            store.updated(),
+        )
+    };
+    let key = match tag {
+        // Feeds of unknown tags aren't cached, so requesting random tags can't fill up memory
+        Some(tag) if !store.has_tag(tag) => return render().respond(headers),
+        Some(tag) => format!("/tags/{tag}/{file}"),
+        None => format!("/{file}"),
+    };
+    store.cached(&key, render).respond(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_conditional_requests() {
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let cached = CachedResponse::new("quack", "text/plain", modified);
+        let response = cached.respond(&HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+        let last_modified = response.headers()[LAST_MODIFIED].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+        assert_eq!(cached.respond(&headers).status(), StatusCode::NOT_MODIFIED);
+        // Some proxies drop the weak marker
+        let strong = cached.etag.trim_start_matches("W/");
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(strong).unwrap());
+        assert_eq!(cached.respond(&headers).status(), StatusCode::NOT_MODIFIED);
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"old\""));
+        // A mismatching ETag wins over a matching date
+        headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        assert_eq!(cached.respond(&headers).status(), StatusCode::OK);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MODIFIED_SINCE, last_modified);
+        assert_eq!(cached.respond(&headers).status(), StatusCode::NOT_MODIFIED);
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Tue, 14 Nov 2023 22:13:19 GMT"),
+        );
+        assert_eq!(cached.respond(&headers).status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cache_control_skips_errors() {
+        use axum::{routing::get, Router};
+        use tower::ServiceExt;
+
+        let app = Router::new()
+            .route("/", get(|| async { "quack" }))
+            .route("/gone", get(|| async { StatusCode::NOT_FOUND }))
+            .layer(cache_control("public, max-age=300"));
+        let request = |path| Request::get(path).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request("/")).await.unwrap();
+        assert_eq!(response.headers()[CACHE_CONTROL], "public, max-age=300");
+        let response = app.oneshot(request("/gone")).await.unwrap();
+        assert!(!response.headers().contains_key(CACHE_CONTROL));
+    }
+
+    #[test]
+    fn test_is_fingerprinted() {
+        assert!(is_fingerprinted("/static/app.3f9a2c1b.css"));
+        assert!(is_fingerprinted("/static/app.min.3f9a2c1b0d.js"));
+        assert!(!is_fingerprinted("/static/highlight.min.js"));
+        assert!(!is_fingerprinted("/static/3f9a2c1b.css"));
+        assert!(!is_fingerprinted("/static/cool_duck.svg"));
+    }
+}
@@ -0,0 +1,123 @@
+//! Command line interface: `duckblog [serve|build|new|check]`
+use crate::config::SiteConfig;
+use crate::post::Post;
+use crate::ssg::DEFAULT_OUT;
+use crate::templates::Templates;
+use crate::utils::slugify;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::Result;
+use regex::Regex;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A blog engine for markdown posts")]
+pub struct Cli {
+    /// Folder containing `posts/`, `images/` and `about.md`, overrides `content_dir` in duckblog.toml
+    #[arg(long, global = true)]
+    pub content_dir: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the blog over HTTP. This is the default when no command is given
+    Serve(ServeArgs),
+    /// Render every page into a static site
+    Build {
+        /// Folder the site is written to. The output of a previous build is deleted
+        #[arg(long, default_value = DEFAULT_OUT)]
+        out: PathBuf,
+        /// Also delete the contents of a non-empty folder that wasn't created by `build`
+        #[arg(long)]
+        force: bool,
+    },
+    /// Create a new draft post from `Templates/New Post Template.md`
+    New {
+        /// Title of the post, also used for the file name and the url
+        title: String,
+    },
+    /// Parse every post and report errors without serving
+    Check,
+}
+
+#[derive(Debug, Parser)]
+pub struct ServeArgs {
+    /// Port to listen on
+    #[arg(long, env = "PORT", default_value_t = 8000)]
+    pub port: u16,
+    /// Address to listen on
+    #[arg(long, default_value = "0.0.0.0")]
+    pub bind: IpAddr,
+    /// Reload open pages in the browser when posts or templates change
+    #[arg(long)]
+    pub dev: bool,
+}
+
+impl ServeArgs {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Serve(ServeArgs::parse_from(["serve"]))
+    }
+}
+
+/// Scaffolds a new post from the post template and returns the path of the created file
+pub fn new_post(title: &str) -> Result<PathBuf> {
+    let config = SiteConfig::get();
+    let template_path = config.content_path("Templates/New Post Template.md");
+    let template = std::fs::read_to_string(&template_path)
+        .wrap_err_with(|| format!("Could not read post template {template_path}"))?;
+    let today = chrono::Local::now().date_naive().format("%Y-%m-%d");
+    let date_re = Regex::new(r#"<% tp\.date\.now\(.*?\) %>"#).unwrap();
+    let url_re = Regex::new(r"(?m)^url:.*$").unwrap();
+    let post = date_re.replace_all(&template, today.to_string());
+    let post = post.replace(
+        "<% tp.file.title %>",
+        &format!("\"{}\"", title.replace('"', "\\\"")),
+    );
+    let post = url_re.replace(&post, format!("url: /posts/{}", slugify(title)));
+
+    let path = PathBuf::from(config.content_path(format!("posts/{}.md", title.replace('/', "-"))));
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+    std::fs::write(&path, post.as_ref())
+        .wrap_err_with(|| format!("Could not write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Parses every post as well as the about page and prints the ones that fail
+pub async fn check_posts() -> Result<()> {
+    Templates::init()?;
+    let config = SiteConfig::get();
+    let posts_dir = config.content_path("posts");
+    let mut files = std::fs::read_dir(&posts_dir)
+        .wrap_err_with(|| format!("Could not read posts directory {posts_dir}"))?
+        .map(|entry| entry.map(|e| e.path().display().to_string()))
+        .collect::<std::io::Result<Vec<String>>>()?;
+    files.sort();
+    files.push(config.content_path("about.md"));
+
+    let mut failed = 0;
+    for file in &files {
+        match Post::load(file.clone()).await {
+            Ok(post) => println!("ok     {} ({})", file, post.path),
+            Err(e) => {
+                failed += 1;
+                eprintln!("error  {file}: {e:#}");
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} files could not be parsed", failed, files.len());
+    }
+    println!("All {} files parsed successfully", files.len());
+    Ok(())
+}
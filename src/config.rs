@@ -0,0 +1,141 @@
+//! Site wide settings loaded from `duckblog.toml`
+use crate::cache::CachePolicies;
+use crate::highlight::Highlighting;
+use crate::sitemap::Robots;
+use crate::theme::Theme;
+use crate::CONFIG;
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::instrument;
+
+/// Where the configuration is read from when nothing else is specified
+pub const CONFIG_PATH: &str = "duckblog.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteConfig {
+    /// Name of the blog, used for feeds and the page titles
+    pub title: String,
+    /// URL the blog is served under, without a trailing slash (https://nereux.blog)
+    pub base_url: String,
+    pub author: String,
+    pub description: String,
+    /// Language tag as used in feeds (en-US)
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Image used for og:image when a page has no images of its own
+    #[serde(default)]
+    pub default_image: Option<String>,
+    /// Folder containing `posts/`, `images/` and `about.md`
+    #[serde(default = "default_content_dir")]
+    pub content_dir: String,
+    /// Number of posts per page of the index and tag pages
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+    /// Number of related posts shown below each post
+    #[serde(default = "default_related_posts")]
+    pub related_posts: usize,
+    /// Show a table of contents on posts that don't set `toc` in their front matter
+    #[serde(default)]
+    pub toc: bool,
+    /// Whether code blocks are highlighted by the server or in the browser
+    #[serde(default)]
+    pub highlighting: Highlighting,
+    /// `Cache-Control` of the different kinds of routes
+    #[serde(default)]
+    pub cache: CachePolicies,
+    /// Rules for crawlers served as `/robots.txt`
+    #[serde(default)]
+    pub robots: Robots,
+    /// Classes and attributes of the html elements in posts
+    #[serde(default)]
+    pub theme: Theme,
+}
+
+fn default_language() -> String {
+    "en-US".to_string()
+}
+
+fn default_content_dir() -> String {
+    "content".to_string()
+}
+
+fn default_page_size() -> usize {
+    10
+}
+
+fn default_related_posts() -> usize {
+    3
+}
+
+impl SiteConfig {
+    /// Returns the loaded configuration, reading it from [`CONFIG_PATH`] on first use.
+    pub fn get() -> &'static SiteConfig {
+        CONFIG.get_or_init(|| {
+            SiteConfig::load(CONFIG_PATH).unwrap_or_else(|e| panic!("Could not load config: {e}"))
+        })
+    }
+
+    #[instrument(skip(path), fields(path = %path.as_ref().display()), err)]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        let mut config: SiteConfig =
+            toml::from_str(&file).map_err(|e| eyre!("{} in {}", e, path.display()))?;
+        config.base_url = config.base_url.trim_end_matches('/').to_string();
+        if config.page_size == 0 {
+            bail!("page_size in {} has to be at least 1", path.display());
+        }
+        Ok(config)
+    }
+
+    /// Path of a file or folder inside the content directory
+    pub fn content_path(&self, path: impl AsRef<str>) -> String {
+        format!(
+            "{}/{}",
+            self.content_dir.trim_end_matches('/'),
+            path.as_ref().trim_start_matches('/')
+        )
+    }
+
+    /// Turns a site relative path like `/posts/foo` into an absolute URL.
+    /// Urls that are absolute already (`https://…`) are returned unchanged
+    pub fn url(&self, path: impl AsRef<str>) -> String {
+        let path = path.as_ref();
+        if path.contains("://") {
+            path.to_string()
+        } else if path.starts_with('/') {
+            format!("{}{}", self.base_url, path)
+        } else {
+            format!("{}/{}", self.base_url, path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let config = SiteConfig::load(CONFIG_PATH).unwrap();
+        assert!(!config.title.is_empty());
+        assert!(!config.base_url.ends_with('/'));
+        assert_eq!(
+            config.url("/posts/bitboard-rust"),
+            format!("{}/posts/bitboard-rust", config.base_url)
+        );
+        assert_eq!(config.url("about"), format!("{}/about", config.base_url));
+        assert_eq!(
+            config.url("https://ducks.dev/a.png"),
+            "https://ducks.dev/a.png"
+        );
+    }
+
+    #[test]
+    fn test_missing_config() {
+        assert!(SiteConfig::load("does-not-exist.toml").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Server side syntax highlighting of code blocks.
+//!
+//! Code is parsed with the syntaxes bundled in syntect and tokens are wrapped in spans with the
+//! class names highlight.js uses, so `static/tokyo-night-dark.min.css` styles them.
+use crate::HIGHLIGHTER;
+use pulldown_cmark_escape::escape_html_body_text;
+use serde::{Deserialize, Serialize};
+use syntect::easy::ScopeRangeIterator;
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxSet};
+use syntect::util::LinesWithEndings;
+use tracing::warn;
+
+/// Where code blocks are highlighted, set as `highlighting` in duckblog.toml
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlighting {
+    /// When rendering the post
+    #[default]
+    Server,
+    /// In the browser by `static/highlight.min.js`
+    Client,
+}
+
+/// Scopes of the syntect syntaxes and the highlight.js classes they map to.
+/// The first scope that prefixes a token's scope wins, so more specific scopes come first.
+const SCOPE_CLASSES: &[(&str, &str)] = &[
+    ("comment", "hljs-comment"),
+    ("string.regexp", "hljs-regexp"),
+    ("string", "hljs-string"),
+    ("constant.numeric", "hljs-number"),
+    ("constant.character.escape", "hljs-char escape_"),
+    ("constant", "hljs-literal"),
+    ("keyword.operator", "hljs-operator"),
+    ("keyword", "hljs-keyword"),
+    ("storage", "hljs-keyword"),
+    ("entity.name.function", "hljs-title function_"),
+    ("entity.name.tag", "hljs-name"),
+    ("entity.name.section", "hljs-section"),
+    ("entity.name", "hljs-title class_"),
+    ("entity.other.attribute-name", "hljs-attr"),
+    (
+        "entity.other.inherited-class",
+        "hljs-title class_ inherited__",
+    ),
+    ("support.function", "hljs-built_in"),
+    ("support.macro", "hljs-built_in"),
+    ("support.type", "hljs-type"),
+    ("support.class", "hljs-type"),
+    ("support.constant", "hljs-literal"),
+    ("variable.language", "hljs-variable language_"),
+    ("variable.parameter", "hljs-params"),
+    ("variable.function", "hljs-title function_"),
+    ("meta.annotation", "hljs-meta"),
+    ("meta.preprocessor", "hljs-meta"),
+    ("markup.heading", "hljs-section"),
+    ("markup.bold", "hljs-strong"),
+    ("markup.italic", "hljs-emphasis"),
+    ("markup.inserted", "hljs-addition"),
+    ("markup.deleted", "hljs-deletion"),
+    ("markup.quote", "hljs-quote"),
+    ("markup.list", "hljs-bullet"),
+    ("markup.underline.link", "hljs-link"),
+];
+
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    classes: Vec<(Scope, &'static str)>,
+}
+
+impl Highlighter {
+    fn get() -> &'static Highlighter {
+        HIGHLIGHTER.get_or_init(|| Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            classes: SCOPE_CLASSES
+                .iter()
+                .map(|(scope, class)| (Scope::new(scope).unwrap(), *class))
+                .collect(),
+        })
+    }
+
+    /// The class of the innermost scope on the stack that has one
+    fn class(&self, stack: &ScopeStack) -> Option<&'static str> {
+        stack.as_slice().iter().rev().find_map(|scope| {
+            self.classes
+                .iter()
+                .find(|(prefix, _)| prefix.is_prefix_of(*scope))
+                .map(|(_, class)| *class)
+        })
+    }
+}
+
+/// Returns the html for the contents of a `<code>` element. Code in languages without a bundled
+/// syntax is only escaped.
+pub fn highlight(code: &str, language: &str) -> String {
+    let highlighter = Highlighter::get();
+    let mut html = String::with_capacity(code.len() * 2);
+    let Some(syntax) = highlighter.syntaxes.find_syntax_by_token(language) else {
+        escape_html_body_text(&mut html, code).unwrap();
+        return html;
+    };
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut open_class = None;
+    let mut lines = LinesWithEndings::from(code);
+    for line in lines.by_ref() {
+        let ops = match state.parse_line(line, &highlighter.syntaxes) {
+            Ok(ops) => ops,
+            Err(e) => {
+                warn!("Could not highlight {language} code: {e}");
+                // Leave the rest of the block as it is
+                escape_html_body_text(&mut html, line).unwrap();
+                break;
+            }
+        };
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            let _ = stack.apply(op);
+            let text = &line[range];
+            if text.is_empty() {
+                continue;
+            }
+            let class = highlighter.class(&stack);
+            if class != open_class {
+                if open_class.is_some() {
+                    html.push_str("</span>");
+                }
+                if let Some(class) = class {
+                    html.push_str(&format!("<span class=\"{class}\">"));
+                }
+                open_class = class;
+            }
+            escape_html_body_text(&mut html, text).unwrap();
+        }
+    }
+    if open_class.is_some() {
+        html.push_str("</span>");
+    }
+    for line in lines {
+        escape_html_body_text(&mut html, line).unwrap();
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_rust() {
+        let html = highlight(
+            "// A duck\nfn quack() -> &'static str { \"<quack>\" }\n",
+            "rust",
+        );
+        assert!(html.contains(r#"<span class="hljs-comment">// A duck"#));
+        assert!(html.contains(r#"<span class="hljs-keyword">fn</span>"#));
+        assert!(html.contains(r#"<span class="hljs-title function_">quack</span>"#));
+        assert!(html.contains("&lt;quack&gt;"));
+    }
+
+    #[test]
+    fn test_unknown_language() {
+        assert_eq!(highlight("a < b", "duckscript"), "a &lt; b");
+    }
+}
@@ -0,0 +1,153 @@
+//! JSON Feed 1.1 (<https://www.jsonfeed.org/version/1.1/>) of all posts, served as `/feed.json`
+use crate::cache::feed_response;
+use crate::config::SiteConfig;
+use crate::post::Post;
+use crate::utils::{feed_title, feed_url, tag_path};
+use axum::extract::Path;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde::Serialize;
+use tracing::instrument;
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+#[derive(Debug, Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: String,
+    home_page_url: String,
+    feed_url: String,
+    description: &'a str,
+    language: &'a str,
+    authors: Vec<Author<'a>>,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Author<'a> {
+    name: &'a str,
+    url: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFeedItem<'a> {
+    id: String,
+    url: String,
+    title: &'a str,
+    content_html: &'a str,
+    summary: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    /// RFC 3339, posts count as published at midnight UTC
+    date_published: String,
+    tags: Vec<&'a str>,
+}
+
+impl<'a> From<&'a Post> for JsonFeedItem<'a> {
+    fn from(post: &'a Post) -> Self {
+        let config = SiteConfig::get();
+        let url = config.url(&post.path);
+        JsonFeedItem {
+            id: url.clone(),
+            url,
+            title: &post.metadata.title,
+            content_html: &post.content,
+            summary: &post.metadata.description,
+            image: post
+                .metadata
+                .images
+                .first()
+                .map(|image| config.url(&image.0)),
+            date_published: post
+                .metadata
+                .date
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .to_rfc3339(),
+            tags: post
+                .metadata
+                .tags
+                .iter()
+                .map(|tag| tag.0.as_str())
+                .collect(),
+        }
+    }
+}
+
+/// Builds the feed from `posts`, which are expected newest first and tagged with `tag` if given
+#[instrument(skip(posts))]
+fn build_json_feed(posts: &[&Post], tag: Option<&str>) -> String {
+    let config = SiteConfig::get();
+    let feed = JsonFeed {
+        version: VERSION,
+        title: feed_title(tag),
+        home_page_url: match tag {
+            Some(tag) => config.url(tag_path(tag)),
+            None => config.url("/"),
+        },
+        feed_url: feed_url(tag, "feed.json"),
+        description: &config.description,
+        language: &config.language,
+        authors: vec![Author {
+            name: &config.author,
+            url: &config.base_url,
+        }],
+        items: posts.iter().map(|post| JsonFeedItem::from(*post)).collect(),
+    };
+    serde_json::to_string_pretty(&feed).unwrap()
+}
+
+pub async fn serve_json_feed(headers: HeaderMap) -> Response {
+    json_feed_response(None, &headers)
+}
+
+pub async fn serve_tag_json_feed(Path(tag): Path<String>, headers: HeaderMap) -> Response {
+    json_feed_response(Some(&tag), &headers)
+}
+
+fn json_feed_response(tag: Option<&str>, headers: &HeaderMap) -> Response {
+    feed_response(
+        tag,
+        "feed.json",
+        "application/feed+json; charset=utf-8",
+        headers,
+        |posts| build_json_feed(posts, tag).into_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::Image;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn test_json_feed() {
+        let mut post = Post::load("content/posts/The Basics.md".to_string())
+            .await
+            .unwrap();
+        let feed: Value = serde_json::from_str(&build_json_feed(&[&post], None)).unwrap();
+        let config = SiteConfig::get();
+
+        assert_eq!(feed["version"], VERSION);
+        assert_eq!(feed["feed_url"], config.url("/feed.json"));
+        let item = &feed["items"][0];
+        assert_eq!(item["url"], config.url("/posts/bitboard-rust"));
+        assert_eq!(item["summary"], post.metadata.description.as_str());
+        assert_eq!(item["tags"][0], "bitboard");
+        assert!(item["content_html"].as_str().unwrap().contains("<p>"));
+        assert_eq!(
+            item["date_published"],
+            format!("{}T00:00:00+00:00", post.metadata.date)
+        );
+        if let Some(image) = post.metadata.images.first() {
+            assert_eq!(item["image"], config.url(&image.0));
+        }
+
+        // Absolute image urls are kept as they are
+        post.metadata.images = vec![Image("https://ducks.dev/duck.png".to_string())];
+        let feed: Value = serde_json::from_str(&build_json_feed(&[&post], None)).unwrap();
+        assert_eq!(feed["items"][0]["image"], "https://ducks.dev/duck.png");
+    }
+}
@@ -0,0 +1,67 @@
+//! Reloads open pages in the browser whenever posts or templates change. Enabled with `serve --dev`.
+use crate::RELOAD;
+use axum::body::{to_bytes, Body};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{middleware, Router};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const ENDPOINT: &str = "/__livereload";
+/// Long polls [`ENDPOINT`] and reloads the page once it answers with 200
+const SCRIPT: &str = r#"<script>
+    (function poll() {
+        fetch("/__livereload")
+            .then((response) => (response.status === 200 ? location.reload() : poll()))
+            .catch(() => setTimeout(poll, 1000));
+    })();
+</script>
+"#;
+
+/// Adds the reload endpoint to `router` and injects the reload script into every html response
+pub fn enable(router: Router) -> Router {
+    let _ = RELOAD.set(broadcast::channel(16).0);
+    router
+        .route(ENDPOINT, get(wait_for_change))
+        .layer(middleware::map_response(inject_script))
+}
+
+/// Reloads all open pages. Does nothing if live reload is not enabled.
+pub fn notify() {
+    if let Some(sender) = RELOAD.get() {
+        let _ = sender.send(());
+    }
+}
+
+/// Answers once something changed or with 204 after 30 seconds so the browser polls again
+async fn wait_for_change() -> StatusCode {
+    let mut receiver = RELOAD.get().unwrap().subscribe();
+    match tokio::time::timeout(Duration::from_secs(30), receiver.recv()).await {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::NO_CONTENT,
+    }
+}
+
+async fn inject_script(response: Response) -> Response {
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !is_html {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let mut html = String::from_utf8_lossy(&bytes).into_owned();
+    match html.rfind("</body>") {
+        Some(index) => html.insert_str(index, SCRIPT),
+        None => html.push_str(SCRIPT),
+    }
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html))
+}
@@ -1,43 +1,126 @@
+mod atom;
+mod cache;
+mod cli;
+mod config;
+mod highlight;
+mod jsonfeed;
+mod livereload;
+mod markdown;
+mod pagination;
 mod post;
+mod related;
 mod rss;
+mod search;
+mod shortcodes;
+mod sitemap;
 mod sponsors;
 mod ssg;
+mod store;
+mod templates;
+mod theme;
 mod utils;
+mod watcher;
 
-use crate::post::Post;
-use crate::rss::serve_rss_feed;
+use crate::atom::{serve_atom_feed, serve_tag_atom_feed};
+use crate::cache::cache_control;
+use crate::cli::{check_posts, new_post, Cli, Command};
+use crate::config::{SiteConfig, CONFIG_PATH};
+use crate::highlight::Highlighter;
+use crate::jsonfeed::{serve_json_feed, serve_tag_json_feed};
+use crate::pagination::{page_url, Pagination};
+use crate::post::{Post, PostMetadata};
+use crate::rss::{serve_rss_feed, serve_tag_rss_feed};
+use crate::sitemap::{serve_robots_txt, serve_sitemap};
 use crate::sponsors::{get_sponsors, noncached_get_sponsors, Sponsor};
 use crate::ssg::generate_static_site;
-use crate::utils::{build_header, liquid_parse};
+use crate::store::{PostStore, TagSort, TagStats};
+use crate::templates::Templates;
+use crate::utils::{build_header, tag_path};
 use axum::body::Body;
-use axum::extract::Path;
-use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse, Redirect, Response};
-use axum::{routing::get, Router};
-use itertools::Itertools;
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Json, Redirect, Response};
+use axum::{middleware, routing::get, Router};
+use chrono::Datelike;
+use clap::Parser;
 use liquid::{object, Object};
-use std::collections::HashMap;
+use serde::Deserialize;
 use std::env;
 use std::error::Error;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use tokio::fs::read_to_string;
 use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tower_http::compression::CompressionLayer;
 use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tracing::*;
 
-pub static POSTS: OnceLock<HashMap<String, Post>> = OnceLock::new();
+pub static CONFIG: OnceLock<SiteConfig> = OnceLock::new();
+pub static POSTS: OnceLock<std::sync::RwLock<Arc<PostStore>>> = OnceLock::new();
 pub static SPONSORS: OnceLock<Arc<RwLock<Vec<Sponsor>>>> = OnceLock::new();
+pub static TEMPLATES: OnceLock<std::sync::RwLock<Arc<Templates>>> = OnceLock::new();
+pub static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+pub static RELOAD: OnceLock<broadcast::Sender<()>> = OnceLock::new();
 
 // TODO: Think about blue/green deployment
 // TODO: Wrapping Code blocks
-// TODO: Create sitemap.xml
-// TODO: add tower-livereload
 fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
     // Read .env
     dotenvy::dotenv().ok();
+    let mut config = SiteConfig::load(CONFIG_PATH)?;
+    if let Some(content_dir) = cli.content_dir {
+        config.content_dir = content_dir;
+    }
+    CONFIG.set(config).unwrap();
+    match cli.command.unwrap_or_default() {
+        Command::New { title } => {
+            let path = new_post(&title)?;
+            println!("Created {}", path.display());
+            Ok(())
+        }
+        Command::Check => Ok(runtime().block_on(check_posts())?),
+        Command::Build { out, force } => run(async move {
+            if cfg!(debug_assertions) {
+                warn!("You are running the SSG in debug mode. This is not recommended.");
+            }
+            generate_static_site(&out, force).await
+        }),
+        Command::Serve(args) => run(async move {
+            // Spawn a task to refresh the sponsors
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(500)).await;
+                    debug!("Refreshing Sponsors");
+                    let new_sponsors = noncached_get_sponsors().await;
+                    if let Ok(sponsors) = new_sponsors {
+                        let mut sponsor_lock = SPONSORS.get().unwrap().write().await;
+                        sponsor_lock.clear();
+                        sponsor_lock.extend(sponsors);
+                    }
+                }
+            });
+            // Keep watching for changes as long as the server runs
+            let _watcher = watcher::watch()?;
+            start_server(args.addr(), args.dev).await;
+            Ok(())
+        }),
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Sets up sentry, tracing and the blog state, then runs `task` to completion
+fn run(task: impl Future<Output = color_eyre::Result<()>>) -> Result<(), Box<dyn Error>> {
     // According to the sentry docs this should be started before the runtime is started
     let _guard = sentry::init((
         env::var("SENTRY_DSN")?,
@@ -55,44 +138,25 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Install the default crypto provider
     rustls::crypto::CryptoProvider::install_default(rustls::crypto::ring::default_provider())
         .unwrap();
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async {
-            // Load Sponsors
-            SPONSORS
-                .set(Arc::new(RwLock::new(
-                    noncached_get_sponsors().await.unwrap_or_default(),
-                )))
-                .unwrap();
-            let _ = Post::parse_all_posts().await.unwrap();
-            // Spawn a task to refresh them
-            tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(Duration::from_secs(500)).await;
-                    debug!("Refreshing Sponsors");
-                    let new_sponsors = noncached_get_sponsors().await;
-                    if let Ok(sponsors) = new_sponsors {
-                        let mut sponsor_lock = SPONSORS.get().unwrap().write().await;
-                        sponsor_lock.clear();
-                        sponsor_lock.extend(sponsors);
-                    }
-                }
-            });
-            start_server().await;
-        });
+    runtime().block_on(async {
+        // Load Sponsors
+        SPONSORS
+            .set(Arc::new(RwLock::new(
+                noncached_get_sponsors().await.unwrap_or_default(),
+            )))
+            .unwrap();
+        // Posts render their shortcodes with the templates
+        Templates::init()?;
+        let _ = Post::parse_all_posts().await?;
+        task.await
+    })?;
     Ok(())
 }
 
-#[instrument]
-async fn start_server() {
-    // Define Routes
-    let app = Router::new()
-        .route(
-            "/security.txt",
-            get(|| async { read_to_string("./security.txt").await.unwrap() }),
-        )
+/// All routes of the blog, shared by the server and the static site generator
+pub(crate) fn router() -> Router {
+    let policies = &SiteConfig::get().cache;
+    let pages = Router::new()
         .route("/posts/{*path}", get(get_post))
         .route(
             "/posts",
@@ -103,14 +167,52 @@ async fn start_server() {
             "/index.html",
             get(|| async { list_posts(Path(String::new())).await }),
         )
-        .route("/tags/{:tag}", get(list_posts))
+        .route("/page/{page}", get(list_page))
+        .route("/archive", get(archive))
+        .route("/archive/{year}", get(archive_year))
+        .route("/archive/{year}/{month}", get(archive_month))
+        .route("/series/{name}", get(get_series))
+        .route("/search", get(search))
+        .route("/search.json", get(search_json))
+        .route("/tags", get(list_tags))
+        .route("/tags.json", get(tags_json))
+        .route("/tags/{tag}", get(list_posts))
+        .route("/tags/{tag}/page/{page}", get(list_tag_page))
         .route("/about", get(get_about))
         .route(
             "/donate",
             get(|| async { get_post(Path("../donate".to_string())).await }),
         )
-        .nest_service("/static", ServeDir::new("static"))
-        .nest_service("/images", ServeDir::new("content/images"))
+        .layer(middleware::from_fn(cache::etag))
+        .layer(cache_control(&policies.pages));
+    let feeds = Router::new()
+        .route("/feed.xml", get(serve_rss_feed))
+        .route("/atom.xml", get(serve_atom_feed))
+        .route("/feed.json", get(serve_json_feed))
+        .route("/tags/{tag}/feed.xml", get(serve_tag_rss_feed))
+        .route("/tags/{tag}/atom.xml", get(serve_tag_atom_feed))
+        .route("/tags/{tag}/feed.json", get(serve_tag_json_feed))
+        .route("/sitemap.xml", get(serve_sitemap))
+        .route("/robots.txt", get(serve_robots_txt))
+        .layer(cache_control(&policies.feeds));
+    let assets = Router::new()
+        .route(
+            "/security.txt",
+            get(|| async { read_to_string("./security.txt").await.unwrap() }),
+        )
+        // Serves `.br` and `.gz` siblings of files to clients that accept them
+        .nest_service(
+            "/static",
+            ServeDir::new("static")
+                .precompressed_br()
+                .precompressed_gzip(),
+        )
+        .nest_service(
+            "/images",
+            ServeDir::new(SiteConfig::get().content_path("images"))
+                .precompressed_br()
+                .precompressed_gzip(),
+        )
         .route(
             "/favicon.ico",
             get(|| async {
@@ -120,48 +222,54 @@ async fn start_server() {
                     .unwrap()
             }),
         )
-        .route("/feed.xml", get(serve_rss_feed))
-        .fallback(handler_404);
+        .layer(middleware::from_fn(cache::static_cache_control));
+    Router::new()
+        .merge(pages)
+        .merge(feeds)
+        .merge(assets)
+        .fallback(handler_404)
+}
 
+#[instrument]
+async fn start_server(addr: SocketAddr, dev: bool) {
     // run our app with hyper
-    let addr = SocketAddr::from((
-        [0, 0, 0, 0],
-        std::env::var("PORT")
-            .unwrap_or("8000".to_string())
-            .parse()
-            .unwrap(),
-    ));
     info!(
         "listening on http://{} in folder {}",
         addr,
         env::current_dir().unwrap().display()
     );
-    if env::args().any(|arg| arg == "--ssg") {
-        if cfg!(debug_assertions) {
-            warn!("You are running the SSG in debug mode. This is not recommended.");
-        }
-        tokio::spawn(generate_static_site());
+    let mut app = router();
+    if dev {
+        info!("Live reload enabled");
+        // Always revalidate so reloading shows the latest version
+        app = livereload::enable(app).layer(SetResponseHeaderLayer::overriding(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("no-cache"),
+        ));
     }
+    // Outermost, so the other layers like the live reload injection see uncompressed bodies
+    app = app.layer(CompressionLayer::new());
     let listener = TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
 #[instrument]
 async fn get_about() -> impl IntoResponse {
-    let template = liquid_parse("post.html.liquid");
-    let about = Post::load("content/about.md".to_string()).await.unwrap();
+    let template = Templates::get("post.html.liquid").unwrap();
+    let about = Post::load(SiteConfig::get().content_path("about.md"))
+        .await
+        .unwrap();
     let sponsors: Vec<Sponsor> = vec![];
-    let header = build_header(Some(about.clone().metadata)).await;
-    let navbar = read_to_string("./liquid/navbar.liquid").await.unwrap();
-    let footer = read_to_string("./liquid/footer.liquid").await.unwrap();
+    let header = build_header(Some(about.clone().metadata), false);
+    let related: Vec<Post> = vec![];
     let globals: Object = object!({
         "post": about,
+        "related": related,
         "header": header,
-        "navbar": navbar,
-        "footer": footer,
         "sponsors": sponsors,
+        "site": SiteConfig::get(),
     });
-    let markup = template.await.render(&globals).unwrap();
+    let markup = template.render(&globals).unwrap();
     Html(markup).into_response()
 }
 
@@ -174,22 +282,24 @@ async fn get_post(Path(path): Path<String>) -> impl IntoResponse {
     // Remove trailing slash
     let path = format!("/posts/{}", path.trim_end_matches('/'));
     debug!("Post `{}` requested", path);
-    let posts_map = POSTS.get().unwrap();
-    let loaded_post = posts_map.get(&path);
+    let store = PostStore::current();
+    let loaded_post = store.get(&path);
     if let Some(post) = loaded_post {
-        let template = liquid_parse("post.html.liquid");
+        let template = Templates::get("post.html.liquid").unwrap();
         let sponsors = get_sponsors().await.unwrap();
-        let header = build_header(Some(post.metadata.clone())).await;
-        let navbar = read_to_string("./liquid/navbar.liquid").await.unwrap();
-        let footer = read_to_string("./liquid/footer.liquid").await.unwrap();
+        let header = build_header(Some(post.metadata.clone()), true);
+        let (previous_post, next_post) = store.neighbours(&post.path);
         let globals: Object = object!({
             "post": post,
+            "related": store.related(&post.path),
+            "series": store.series_position(post),
+            "previous_post": previous_post,
+            "next_post": next_post,
             "header": header,
-            "navbar": navbar,
-            "footer": footer,
             "sponsors": sponsors,
+            "site": SiteConfig::get(),
         });
-        let markup = template.await.render(&globals).unwrap();
+        let markup = template.render(&globals).unwrap();
         Html(markup).into_response()
     } else {
         debug!("Post not found because: {:#?}", loaded_post);
@@ -197,28 +307,194 @@ async fn get_post(Path(path): Path<String>) -> impl IntoResponse {
     }
 }
 #[instrument]
-async fn list_posts(Path(path): Path<String>) -> impl IntoResponse {
-    info!("Listing posts with filter: {:#?}", path);
-    let mut posts = POSTS
-        .get()
-        .unwrap()
-        .values()
-        .sorted_by_key(|p| p.metadata.date)
-        .cloned()
-        .collect::<Vec<Post>>();
-    posts.reverse();
-    if !path.is_empty() {
-        posts.retain(|post| post.metadata.tags.iter().any(|tag| tag == &path));
+async fn list_posts(Path(path): Path<String>) -> Response {
+    let tag = (!path.is_empty()).then_some(path.as_str());
+    render_listing(tag, 1).await
+}
+
+#[instrument]
+async fn list_page(Path(page): Path<usize>) -> Response {
+    if page == 1 {
+        return Redirect::permanent(&page_url(None, 1)).into_response();
+    }
+    render_listing(None, page).await
+}
+
+#[instrument]
+async fn list_tag_page(Path((tag, page)): Path<(String, usize)>) -> Response {
+    if page == 1 {
+        return Redirect::permanent(&page_url(Some(&tag), 1)).into_response();
+    }
+    render_listing(Some(&tag), page).await
+}
+
+#[derive(Debug, Deserialize)]
+struct TagsQuery {
+    #[serde(default)]
+    sort: TagSort,
+}
+
+#[instrument]
+async fn list_tags(Query(query): Query<TagsQuery>) -> Response {
+    let template = Templates::get("tags.html.liquid").unwrap();
+    let tags = PostStore::current().tags(query.sort);
+    let header = build_header(
+        Some(PostMetadata {
+            title: format!("Tags - {}", SiteConfig::get().title),
+            url: "/tags".to_string(),
+            ..PostMetadata::default()
+        }),
+        false,
+    );
+    let globals: Object = object!({
+        "tags": tags,
+        "sort": query.sort,
+        "header": header,
+        "site": SiteConfig::get(),
+    });
+    let markup = template.render(&globals).unwrap();
+    Html(markup).into_response()
+}
+
+async fn tags_json(Query(query): Query<TagsQuery>) -> Json<Vec<TagStats>> {
+    Json(PostStore::current().tags(query.sort))
+}
+
+#[instrument]
+async fn archive() -> Response {
+    render_archive(None, None).await
+}
+
+#[instrument]
+async fn archive_year(Path(year): Path<i32>) -> Response {
+    render_archive(Some(year), None).await
+}
+
+#[instrument]
+async fn archive_month(Path((year, month)): Path<(i32, u32)>) -> Response {
+    render_archive(Some(year), Some(month)).await
+}
+
+/// Renders the posts of a year or month, or all of them. Periods without posts don't exist.
+async fn render_archive(year: Option<i32>, month: Option<u32>) -> Response {
+    let store = PostStore::current();
+    let posts = store
+        .newest_first(None)
+        .into_iter()
+        .filter(|post| year.is_none() || year == Some(post.metadata.date.year()))
+        .filter(|post| month.is_none() || month == Some(post.metadata.date.month()))
+        .collect::<Vec<_>>();
+    if let (Some(year), true) = (year, posts.is_empty()) {
+        let path = match month {
+            Some(month) => format!("/archive/{year}/{month:02}"),
+            None => format!("/archive/{year}"),
+        };
+        return handler_404(path).await.into_response();
     }
-    let navbar = read_to_string("./liquid/navbar.liquid").await.unwrap();
-    let footer = read_to_string("./liquid/footer.liquid").await.unwrap();
-    let template = info_span!("liquid.parse").in_scope(|| liquid_parse("index.html.liquid"));
-    let globals: Object = object!({ "posts": posts,
-            "navbar": navbar,
-            "footer": footer });
-    let markup = info_span!("liquid.render")
-        .in_scope(|| async { template.await.render(&globals).unwrap() })
-        .await;
+    let title = match (year, month, posts.first()) {
+        (Some(_), Some(_), Some(post)) => post.metadata.date.format("%B %Y").to_string(),
+        (Some(year), _, _) => year.to_string(),
+        _ => "Archive".to_string(),
+    };
+    let template = Templates::get("archive.html.liquid").unwrap();
+    let header = build_header(
+        Some(PostMetadata {
+            title: format!("{title} - {}", SiteConfig::get().title),
+            url: "/archive".to_string(),
+            ..PostMetadata::default()
+        }),
+        false,
+    );
+    let globals: Object = object!({
+        "title": title,
+        "years": store.archive(),
+        "selected_year": year,
+        "selected_month": month,
+        "posts": posts,
+        "header": header,
+        "site": SiteConfig::get(),
+    });
+    let markup = template.render(&globals).unwrap();
+    Html(markup).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[instrument]
+async fn search(Query(query): Query<SearchQuery>) -> Response {
+    let store = PostStore::current();
+    let results = store.search(&query.q);
+    let template = Templates::get("search.html.liquid").unwrap();
+    let header = build_header(
+        Some(PostMetadata {
+            title: format!("Search - {}", SiteConfig::get().title),
+            url: "/search".to_string(),
+            ..PostMetadata::default()
+        }),
+        false,
+    );
+    let globals: Object = object!({
+        "query": query.q,
+        "results": results,
+        "header": header,
+        "site": SiteConfig::get(),
+    });
+    let markup = template.render(&globals).unwrap();
+    Html(markup).into_response()
+}
+
+/// The search index for searching in the browser when the site is served statically
+async fn search_json() -> Response {
+    Json(PostStore::current().search_index()).into_response()
+}
+
+#[instrument]
+async fn get_series(Path(slug): Path<String>) -> Response {
+    let store = PostStore::current();
+    let Some(series) = store.series(&slug) else {
+        return handler_404(format!("/series/{slug}")).await.into_response();
+    };
+    let template = Templates::get("series.html.liquid").unwrap();
+    let header = build_header(
+        Some(PostMetadata {
+            title: format!("{} - {}", series.name, SiteConfig::get().title),
+            url: series.url.clone(),
+            ..PostMetadata::default()
+        }),
+        false,
+    );
+    let globals: Object = object!({
+        "series": series,
+        "posts": store.series_posts(series),
+        "header": header,
+        "site": SiteConfig::get(),
+    });
+    let markup = template.render(&globals).unwrap();
+    Html(markup).into_response()
+}
+
+/// Renders page `page` of all posts or those tagged with `tag`
+async fn render_listing(tag: Option<&str>, page: usize) -> Response {
+    info!("Listing page {page} of posts with filter: {:#?}", tag);
+    let store = PostStore::current();
+    let posts = store.newest_first(tag);
+    let Some(pagination) = Pagination::new(tag, page, posts.len()) else {
+        return handler_404(page_url(tag, page)).await.into_response();
+    };
+    let template = Templates::get("index.html.liquid").unwrap();
+    let globals: Object = object!({ "posts": pagination.slice(&posts),
+            "tag": tag,
+            "tag_url": tag.map(tag_path),
+            "page": pagination.page,
+            "pages": pagination.pages,
+            "prev": pagination.prev,
+            "next": pagination.next,
+            "site": SiteConfig::get() });
+    let markup = info_span!("liquid.render").in_scope(|| template.render(&globals).unwrap());
     Html(markup).into_response()
 }
 
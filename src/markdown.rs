@@ -0,0 +1,435 @@
+//! Transformations on the pulldown-cmark event stream of a post
+use crate::highlight::{highlight, Highlighting};
+use crate::theme::Theme;
+use pulldown_cmark::{
+    Alignment, BlockQuoteKind, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Tag, TagEnd,
+};
+use pulldown_cmark_escape::{escape_href, escape_html, escape_html_body_text};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A heading of a post and the id it can be linked with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heading {
+    /// 1 for `#`, 2 for `##` ...
+    pub level: u8,
+    /// Id of the heading in the rendered html, so it can be linked as `#id`
+    pub id: String,
+    pub title: String,
+}
+
+/// A heading of a post with the headings nested below it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Gives every heading a unique id and a permalink to itself and returns all headings in order.
+///
+/// Ids are GitHub compatible slugs of the heading text, explicit `{#id}`s are kept.
+pub fn assign_heading_ids(events: &mut Vec<Event>) -> Vec<Heading> {
+    let mut used_ids: HashMap<String, usize> = HashMap::new();
+    // Explicit ids are reserved first so generated ones can't collide with them
+    for event in events.iter() {
+        if let Event::Start(Tag::Heading { id: Some(id), .. }) = event {
+            used_ids.insert(id.to_string(), 1);
+        }
+    }
+    let mut headings = Vec::new();
+    let mut index = 0;
+    while index < events.len() {
+        let Event::Start(Tag::Heading { level, .. }) = &events[index] else {
+            index += 1;
+            continue;
+        };
+        let level = heading_level(*level);
+        let start = index;
+        let mut title = String::new();
+        while !matches!(events[index], Event::End(TagEnd::Heading(_))) {
+            if let Event::Text(text) | Event::Code(text) = &events[index] {
+                title.push_str(text);
+            }
+            index += 1;
+        }
+        if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+            let slug = match id {
+                Some(existing) => existing.to_string(),
+                None => unique_id(slugify_heading(&title), &mut used_ids),
+            };
+            *id = Some(CowStr::from(slug.clone()));
+            events.insert(index, Event::InlineHtml(permalink(&slug).into()));
+            index += 1;
+            headings.push(Heading {
+                level,
+                id: slug,
+                title: title.trim().to_string(),
+            });
+        }
+    }
+    headings
+}
+
+/// Anchor shown next to a heading on hover
+fn permalink(id: &str) -> String {
+    format!(r##"<a class="heading-anchor" href="#{id}" aria-label="Link to this section">#</a>"##)
+}
+
+/// Nests the headings below the previous heading with a lower level
+pub fn table_of_contents(headings: &[Heading]) -> Vec<TocEntry> {
+    nest(&mut headings.iter().cloned().peekable(), 0)
+}
+
+/// Builds the tree of all headings deeper than `parent_level`
+fn nest(
+    headings: &mut std::iter::Peekable<impl Iterator<Item = Heading>>,
+    parent_level: u8,
+) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    while let Some(heading) = headings.next_if(|heading| heading.level > parent_level) {
+        let children = nest(headings, heading.level);
+        entries.push(TocEntry {
+            level: heading.level,
+            id: heading.id,
+            title: heading.title,
+            children,
+        });
+    }
+    entries
+}
+
+/// Renders the elements styled by a [`Theme`] to html with the configured classes and attributes.
+///
+/// Code blocks are highlighted here unless `highlighting` leaves it to the browser.
+/// All other events, e.g. text, footnotes and inline html, are left for `pulldown_cmark::html`.
+pub fn apply_theme<'a>(
+    events: Vec<Event<'a>>,
+    theme: &Theme,
+    highlighting: Highlighting,
+) -> Vec<Event<'a>> {
+    let mut themed = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    let mut table_alignments = Vec::new();
+    let mut in_table_head = false;
+    let mut cell_index = 0;
+    while let Some(event) = events.next() {
+        let html = match event {
+            Event::Start(Tag::Heading {
+                level,
+                id,
+                mut classes,
+                mut attrs,
+            }) => {
+                let element = format!("h{}", heading_level(level));
+                classes.extend(theme.class(&element).map(|class| class.to_string().into()));
+                attrs.extend(
+                    theme
+                        .extra_attributes(&element)
+                        .map(|(name, value)| (name.clone().into(), Some(value.clone().into()))),
+                );
+                themed.push(Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                }));
+                continue;
+            }
+            Event::Start(Tag::Paragraph) => format!("<p{}>", theme.attributes("p", &[])),
+            Event::End(TagEnd::Paragraph) => "</p>\n".to_string(),
+            Event::Start(Tag::BlockQuote(kind)) => {
+                let alert = kind.map(|kind| match kind {
+                    BlockQuoteKind::Note => "markdown-alert-note",
+                    BlockQuoteKind::Tip => "markdown-alert-tip",
+                    BlockQuoteKind::Important => "markdown-alert-important",
+                    BlockQuoteKind::Warning => "markdown-alert-warning",
+                    BlockQuoteKind::Caution => "markdown-alert-caution",
+                });
+                let classes = alert.as_slice();
+                format!(
+                    "\n<blockquote{}>\n",
+                    theme.attributes("blockquote", classes)
+                )
+            }
+            Event::End(TagEnd::BlockQuote(_)) => "</blockquote>\n".to_string(),
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match &kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split([' ', ',']).next().unwrap_or_default()
+                    }
+                    CodeBlockKind::Indented => "",
+                };
+                let language_class = format!("language-{language}");
+                let mut classes = if language.is_empty() {
+                    vec![]
+                } else {
+                    vec![language_class.as_str()]
+                };
+                if highlighting == Highlighting::Client {
+                    format!(
+                        "\n<pre{}><code{}>",
+                        theme.attributes("pre", &[]),
+                        theme.attributes("code_block", &classes)
+                    )
+                } else {
+                    let mut code = String::new();
+                    for event in events.by_ref() {
+                        match event {
+                            Event::End(TagEnd::CodeBlock) => break,
+                            Event::Text(text) => code.push_str(&text),
+                            _ => {}
+                        }
+                    }
+                    classes.insert(0, "hljs");
+                    format!(
+                        "\n<pre{}><code{}>{}</code></pre>\n",
+                        theme.attributes("pre", &[]),
+                        theme.attributes("code_block", &classes),
+                        highlight(&code, language)
+                    )
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => "</code></pre>\n".to_string(),
+            Event::Start(Tag::List(Some(start))) => {
+                let start = if start == 1 {
+                    String::new()
+                } else {
+                    format!(" start=\"{start}\"")
+                };
+                format!("\n<ol{start}{}>\n", theme.attributes("ol", &[]))
+            }
+            Event::Start(Tag::List(None)) => format!("\n<ul{}>\n", theme.attributes("ul", &[])),
+            Event::End(TagEnd::List(true)) => "</ol>\n".to_string(),
+            Event::End(TagEnd::List(false)) => "</ul>\n".to_string(),
+            Event::Start(Tag::Item) => format!("<li{}>", theme.attributes("li", &[])),
+            Event::End(TagEnd::Item) => "</li>\n".to_string(),
+            Event::Start(Tag::Table(alignments)) => {
+                table_alignments = alignments;
+                format!("<table{}>", theme.attributes("table", &[]))
+            }
+            Event::End(TagEnd::Table) => "</tbody></table>\n".to_string(),
+            Event::Start(Tag::TableHead) => {
+                in_table_head = true;
+                cell_index = 0;
+                format!(
+                    "<thead{}><tr{}>",
+                    theme.attributes("thead", &[]),
+                    theme.attributes("tr", &[])
+                )
+            }
+            Event::End(TagEnd::TableHead) => {
+                in_table_head = false;
+                format!("</tr></thead><tbody{}>\n", theme.attributes("tbody", &[]))
+            }
+            Event::Start(Tag::TableRow) => {
+                cell_index = 0;
+                format!("<tr{}>", theme.attributes("tr", &[]))
+            }
+            Event::End(TagEnd::TableRow) => "</tr>\n".to_string(),
+            Event::Start(Tag::TableCell) => {
+                let element = if in_table_head { "th" } else { "td" };
+                let alignment = match table_alignments.get(cell_index) {
+                    Some(Alignment::Left) => " style=\"text-align: left\"",
+                    Some(Alignment::Center) => " style=\"text-align: center\"",
+                    Some(Alignment::Right) => " style=\"text-align: right\"",
+                    _ => "",
+                };
+                format!("<{element}{alignment}{}>", theme.attributes(element, &[]))
+            }
+            Event::End(TagEnd::TableCell) => {
+                cell_index += 1;
+                if in_table_head { "</th>" } else { "</td>" }.to_string()
+            }
+            Event::Start(Tag::Emphasis) => format!("<em{}>", theme.attributes("em", &[])),
+            Event::End(TagEnd::Emphasis) => "</em>".to_string(),
+            Event::Start(Tag::Strong) => format!("<strong{}>", theme.attributes("strong", &[])),
+            Event::End(TagEnd::Strong) => "</strong>".to_string(),
+            Event::Start(Tag::Strikethrough) => format!("<del{}>", theme.attributes("del", &[])),
+            Event::End(TagEnd::Strikethrough) => "</del>".to_string(),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                ..
+            }) => {
+                let mut html = String::from("<a href=\"");
+                if link_type == LinkType::Email {
+                    html.push_str("mailto:");
+                }
+                escape_href(&mut html, &dest_url).unwrap();
+                html.push('"');
+                push_title(&mut html, &title);
+                html.push_str(&theme.attributes("a", &[]));
+                html.push('>');
+                html
+            }
+            Event::End(TagEnd::Link) => "</a>".to_string(),
+            Event::Start(Tag::Image {
+                dest_url, title, ..
+            }) => {
+                // The alt text is made of all text up to the end of the image
+                let mut alt = String::new();
+                let mut depth = 0;
+                for event in events.by_ref() {
+                    match event {
+                        Event::Start(Tag::Image { .. }) => depth += 1,
+                        Event::End(TagEnd::Image) if depth == 0 => break,
+                        Event::End(TagEnd::Image) => depth -= 1,
+                        Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                        _ => {}
+                    }
+                }
+                let mut html = String::from("<img src=\"");
+                escape_href(&mut html, &dest_url).unwrap();
+                html.push_str("\" alt=\"");
+                escape_html(&mut html, &alt).unwrap();
+                html.push('"');
+                push_title(&mut html, &title);
+                html.push_str(&theme.attributes("img", &[]));
+                html.push_str(" />");
+                html
+            }
+            Event::Code(text) => {
+                let mut html = format!("<code{}>", theme.attributes("code", &[]));
+                escape_html_body_text(&mut html, &text).unwrap();
+                html.push_str("</code>");
+                html
+            }
+            Event::Rule => format!("\n<hr{} />\n", theme.attributes("hr", &[])),
+            event => {
+                themed.push(event);
+                continue;
+            }
+        };
+        themed.push(Event::InlineHtml(html.into()));
+    }
+    themed
+}
+
+fn push_title(html: &mut String, title: &str) {
+    if !title.is_empty() {
+        html.push_str(" title=\"");
+        escape_html(&mut *html, title).unwrap();
+        html.push('"');
+    }
+}
+
+fn heading_level(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+/// Slugs headings like GitHub does: lowercase, punctuation removed and spaces replaced by `-`
+pub fn slugify_heading(title: &str) -> String {
+    title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Appends `-1`, `-2` ... to ids that were used before in the same post
+fn unique_id(slug: String, used_ids: &mut HashMap<String, usize>) -> String {
+    let slug = if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    };
+    let mut id = slug.clone();
+    while let Some(count) = used_ids.get_mut(&id) {
+        id = format!("{slug}-{count}");
+        *count += 1;
+    }
+    used_ids.insert(id.clone(), 1);
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    #[test]
+    fn test_slugify_heading() {
+        assert_eq!(slugify_heading("Why BitBoards?"), "why-bitboards");
+        assert_eq!(slugify_heading("Thanks to:"), "thanks-to");
+        assert_eq!(slugify_heading("snake_case & more"), "snake_case--more");
+    }
+
+    #[test]
+    fn test_toc() {
+        let markdown = "# Intro\n## Setup\n### `cargo` install\n## Setup\n# Conclusion {#end}\n";
+        let mut events = Parser::new_ext(markdown, Options::all()).collect::<Vec<_>>();
+        let toc = table_of_contents(&assign_heading_ids(&mut events));
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].id, "intro");
+        let setup = &toc[0].children;
+        assert_eq!(setup.len(), 2);
+        assert_eq!(setup[0].children[0].id, "cargo-install");
+        assert_eq!(setup[0].children[0].title, "cargo install");
+        // Duplicate headings get a suffix
+        assert_eq!(setup[1].id, "setup-1");
+        // Explicit ids are kept
+        assert_eq!(toc[1].id, "end");
+    }
+
+    #[test]
+    fn test_heading_ids() {
+        let markdown = "## End\n# Conclusion {#end}\n## 🦆\n";
+        let mut events = Parser::new_ext(markdown, Options::all()).collect::<Vec<_>>();
+        let ids = assign_heading_ids(&mut events)
+            .into_iter()
+            .map(|heading| heading.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["end-1", "end", "section"]);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains(r##"<h2 id="end-1">End<a class="heading-anchor" href="#end-1""##));
+    }
+
+    #[test]
+    fn test_apply_theme() {
+        let theme: Theme = toml::from_str(
+            r#"
+            a = "link"
+            h3 = "small"
+            ol = "numbered"
+            code_block = "block"
+            img = { class = "center", attributes = { loading = "lazy" } }
+            "#,
+        )
+        .unwrap();
+        let markdown = "### Title\n\n3. [a & b](https://example.com \"Title\")\n\n```rust\nlet a = \"<a \";\n```\n\n![An *image*](/images/duck.avif)\n";
+        let events = Parser::new_ext(markdown, Options::all()).collect::<Vec<_>>();
+        let events = apply_theme(events, &theme, Highlighting::Client);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains(r#"<h3 class="small">Title</h3>"#));
+        assert!(html.contains(r#"<ol start="3" class="numbered">"#));
+        assert!(html
+            .contains(r#"<a href="https://example.com" title="Title" class="link">a &amp; b</a>"#));
+        // Code is not touched by link styling
+        assert!(html.contains(r#"<pre><code class="language-rust block">let a = "&lt;a ";"#));
+        assert!(html.contains(
+            r#"<img src="/images/duck.avif" alt="An image" class="center" loading="lazy" />"#
+        ));
+
+        let events = Parser::new_ext(markdown, Options::all()).collect::<Vec<_>>();
+        let events = apply_theme(events, &theme, Highlighting::Server);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert!(html.contains(
+            r#"<pre><code class="hljs language-rust block"><span class="hljs-keyword">let</span>"#
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+//! Splits post listings into pages of [`SiteConfig::page_size`] posts
+use crate::config::SiteConfig;
+use crate::utils::tag_path;
+use serde::Serialize;
+
+/// Position of a listing page, passed to `index.html.liquid`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Pagination {
+    /// Starts at 1
+    pub page: usize,
+    pub pages: usize,
+    /// Url of the previous page, if there is one
+    pub prev: Option<String>,
+    /// Url of the next page, if there is one
+    pub next: Option<String>,
+}
+
+impl Pagination {
+    /// Returns `None` if `page` doesn't exist for `posts` posts
+    pub fn new(tag: Option<&str>, page: usize, posts: usize) -> Option<Self> {
+        let pages = page_count(posts);
+        if page == 0 || page > pages {
+            return None;
+        }
+        Some(Pagination {
+            page,
+            pages,
+            prev: (page > 1).then(|| page_url(tag, page - 1)),
+            next: (page < pages).then(|| page_url(tag, page + 1)),
+        })
+    }
+
+    /// The posts of this page out of all `posts`
+    pub fn slice<'a, T>(&self, posts: &'a [T]) -> &'a [T] {
+        let size = SiteConfig::get().page_size;
+        let start = (self.page - 1) * size;
+        &posts[start.min(posts.len())..(start + size).min(posts.len())]
+    }
+}
+
+/// Number of pages needed for `posts` posts. Empty listings still have one page.
+pub fn page_count(posts: usize) -> usize {
+    posts.div_ceil(SiteConfig::get().page_size).max(1)
+}
+
+/// Url of a listing page, the first one is served without a page number
+pub fn page_url(tag: Option<&str>, page: usize) -> String {
+    match (tag, page) {
+        (None, 1) => "/".to_string(),
+        (None, page) => format!("/page/{page}"),
+        (Some(tag), 1) => tag_path(tag),
+        (Some(tag), page) => format!("{}/page/{page}", tag_path(tag)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        let size = SiteConfig::get().page_size;
+        let posts = (0..size * 2 + 1).collect::<Vec<_>>();
+        assert_eq!(page_count(posts.len()), 3);
+        assert_eq!(page_count(0), 1);
+
+        let first = Pagination::new(None, 1, posts.len()).unwrap();
+        assert_eq!(first.prev, None);
+        assert_eq!(first.next.as_deref(), Some("/page/2"));
+        assert_eq!(first.slice(&posts), &posts[..size]);
+
+        let last = Pagination::new(Some("rust"), 3, posts.len()).unwrap();
+        assert_eq!(last.prev.as_deref(), Some("/tags/rust/page/2"));
+        assert_eq!(last.next, None);
+        assert_eq!(last.slice(&posts), &[size * 2]);
+
+        assert_eq!(page_url(Some("no std"), 2), "/tags/no%20std/page/2");
+
+        assert!(Pagination::new(None, 0, posts.len()).is_none());
+        assert!(Pagination::new(None, 4, posts.len()).is_none());
+    }
+}
@@ -1,15 +1,19 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
 use crate::{
-    utils::{get_reading_time, liquid_parse},
-    POSTS,
+    config::SiteConfig,
+    markdown::{self, Heading, TocEntry},
+    shortcodes,
+    store::PostStore,
+    utils::{get_reading_time, slugify, tag_path},
 };
 use chrono::NaiveDate;
-use color_eyre::eyre::anyhow;
+use color_eyre::eyre::{bail, eyre, WrapErr};
 use color_eyre::Result;
 use pulldown_cmark::{html, Options, Parser};
 use regex::Regex;
 use rss::{Category, CategoryBuilder, Item as RssItem, ItemBuilder};
+use serde::de::DeserializeOwned;
 use tokio::fs::read_to_string;
 use tracing::*;
 
@@ -19,6 +23,10 @@ pub struct Post {
     pub content: String,
     pub path: String,
     pub metadata: PostMetadata,
+    /// All headings with their ids in the order they appear
+    pub headings: Vec<Heading>,
+    /// Headings of the post, shown as table of contents if `metadata.toc` is set
+    pub toc: Vec<TocEntry>,
 }
 
 #[allow(clippy::from_over_into)]
@@ -34,7 +42,7 @@ impl Into<RssItem> for Post {
             .to_rfc2822();
         ItemBuilder::default()
             .title(Some(self.metadata.title))
-            .link(Some(format!("https://nereux.blog{}", self.path)))
+            .link(Some(SiteConfig::get().url(&self.path)))
             .description(Some(self.metadata.description))
             .content(self.content)
             .pub_date(Some(rfc2822_date))
@@ -54,10 +62,24 @@ impl Into<RssItem> for Post {
     }
 }
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-pub struct Image(String);
+pub struct Image(pub String);
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct Tag(pub String);
+
+/// Templates get the name and the percent-encoded url of the tag page
+impl serde::Serialize for Tag {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut tag = serializer.serialize_struct("Tag", 2)?;
+        tag.serialize_field("name", &self.0)?;
+        tag.serialize_field("url", &tag_path(&self.0))?;
+        tag.end()
+    }
+}
 impl Tag {
     pub fn from_str(s: &str) -> Self {
         Tag(s.to_string())
@@ -78,7 +100,7 @@ impl PartialEq<String> for Tag {
 
 impl Tag {
     pub fn get_url(&self) -> String {
-        format!("https://nereux.blog/tags/{}", self.0)
+        SiteConfig::get().url(tag_path(&self.0))
     }
 }
 
@@ -93,23 +115,123 @@ pub struct PostMetadata {
     pub url: String,
     pub time_to_read: Option<usize>,
     pub images: Vec<Image>,
+    /// Whether to show a table of contents
+    pub toc: bool,
+    /// Name of the series this post is part of
+    pub series: Option<String>,
+    /// Position in the series, posts without one are ordered by date after those with one
+    pub series_order: Option<u32>,
 }
 
 impl PostMetadata {
-    fn parse(input: impl AsRef<str> + Display, text: impl AsRef<str> + Display) -> Result<Self> {
-        let metadata_builder: PostMetadataBuilder = serde_yaml_ng::from_str(input.as_ref())
-            .map_err(|e| anyhow!("{} in this metadata:\n{}", e.to_string(), input))?;
-        let ttr = Some(get_reading_time(text.as_ref()));
-        Ok(PostMetadata {
+    fn new(metadata_builder: PostMetadataBuilder, path: &str, text: &str) -> Self {
+        let ttr = Some(get_reading_time(text));
+        PostMetadata {
             title: metadata_builder.title,
             date: metadata_builder.date,
             tags: metadata_builder.tags,
             draft: metadata_builder.draft,
             description: metadata_builder.description,
-            url: metadata_builder.url,
+            url: metadata_builder.url.unwrap_or_else(|| url_from_path(path)),
             time_to_read: ttr,
-            images: Post::load_images(text.as_ref()),
-        })
+            images: Post::load_images(text),
+            toc: metadata_builder.toc.unwrap_or(SiteConfig::get().toc),
+            series: metadata_builder.series,
+            series_order: metadata_builder.series_order,
+        }
+    }
+}
+
+/// Url of a post without `url` in its front matter. Hugo style `bitboard-rust/index.md` is
+/// served as `/posts/bitboard-rust`, other files by the slug of their name
+fn url_from_path(path: &str) -> String {
+    let path = std::path::Path::new(path);
+    let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some("index") | None => path.parent().and_then(|dir| dir.file_name()),
+        Some(_) => path.file_stem(),
+    };
+    let name = name.map(|name| name.to_string_lossy()).unwrap_or_default();
+    format!("/posts/{}", slugify(&name))
+}
+
+/// Format of the metadata block at the start of a post
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrontMatterFormat {
+    Yaml,
+    Toml,
+}
+
+impl FrontMatterFormat {
+    fn delimiter(self) -> &'static str {
+        match self {
+            FrontMatterFormat::Yaml => "---",
+            FrontMatterFormat::Toml => "+++",
+        }
+    }
+}
+
+/// A post split into its front matter and markdown body
+#[derive(Debug)]
+struct FrontMatter<'a> {
+    format: FrontMatterFormat,
+    /// Everything up to the closing delimiter, including the opening one
+    block: &'a str,
+    /// Byte offset of the metadata in `block`
+    start: usize,
+    body: &'a str,
+}
+
+impl<'a> FrontMatter<'a> {
+    /// Split off the leading `---` (YAML) or `+++` (TOML) block,
+    /// delimiters further down are horizontal rules in the body
+    fn split(path: &str, file: &'a str) -> Result<Self> {
+        let file = file.strip_prefix('\u{feff}').unwrap_or(file);
+        let mut lines = file.split_inclusive('\n');
+        let first = lines.next().unwrap_or_default();
+        let format = match first.trim_end() {
+            "---" => FrontMatterFormat::Yaml,
+            "+++" => FrontMatterFormat::Toml,
+            _ => bail!(
+                "{path}:1: missing front matter, posts have to start with a `---` (YAML) or `+++` (TOML) line"
+            ),
+        };
+        let mut end = first.len();
+        for line in lines {
+            if line.trim_end() == format.delimiter() {
+                return Ok(FrontMatter {
+                    format,
+                    block: &file[..end],
+                    start: first.len(),
+                    body: &file[end + line.len()..],
+                });
+            }
+            end += line.len();
+        }
+        bail!(
+            "{path}:1: front matter opened with `{}` is never closed",
+            format.delimiter()
+        )
+    }
+
+    /// Line of the file the body starts on
+    fn body_line(&self) -> usize {
+        self.block.matches('\n').count() + 2
+    }
+
+    /// Deserialize the metadata, errors point to the line in the post
+    fn parse<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        match self.format {
+            // A leading `---` is a YAML document marker, so line numbers match the file
+            FrontMatterFormat::Yaml => serde_yaml_ng::from_str(self.block).map_err(|e| {
+                let line = e.location().map_or(1, |l| l.line());
+                eyre!("{path}:{line}: invalid YAML front matter: {e}")
+            }),
+            FrontMatterFormat::Toml => toml::from_str(&self.block[self.start..]).map_err(|e| {
+                let offset = self.start + e.span().map_or(0, |span| span.start);
+                let line = self.block[..offset].matches('\n').count() + 1;
+                eyre!("{path}:{line}: invalid TOML front matter: {}", e.message())
+            }),
+        }
     }
 }
 
@@ -124,21 +246,34 @@ pub struct PostMetadataBuilder {
     pub draft: bool,
     #[serde(default)]
     pub description: String,
-    pub url: String,
+    /// Derived from the file name if not set, see [`url_from_path`]
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Falls back to `toc` in duckblog.toml
+    #[serde(default)]
+    pub toc: Option<bool>,
+    #[serde(default)]
+    pub series: Option<String>,
+    #[serde(default)]
+    pub series_order: Option<u32>,
 }
 
 impl Default for PostMetadata {
     fn default() -> Self {
+        let config = SiteConfig::get();
         PostMetadata {
-            title: "DuckBlog".to_string(),
+            title: config.title.clone(),
             date: "2021-08-23".parse::<NaiveDate>().unwrap(),
             tags: vec![Tag::from_str("Duck"), Tag::from_str("Blog")],
             draft: false,
-            description: "Nereuxofficial's blog about mostly Rust".to_string(),
+            description: config.description.clone(),
             time_to_read: Some(1337),
             url: "/".to_string(),
             // TODO: Customize this for the main page. Maybe the image of the latest post?
-            images: vec![],
+            images: config.default_image.iter().cloned().map(Image).collect(),
+            toc: false,
+            series: None,
+            series_order: None,
         }
     }
 }
@@ -161,36 +296,29 @@ impl Post {
             path = path.replace("//", "/");
         }
         trace!("Parsing post `{}`", path);
-        let file = read_to_string(path).await?;
+        let file = read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read post {path}"))?;
         // Split content from metadata
-        let mut content_split_iterator = file.split("---");
-        let metadata_part = content_split_iterator.nth(1).unwrap();
-        let text = content_split_iterator.next().unwrap();
-        let metadata = PostMetadata::parse(metadata_part, text)?;
-        // Before Parsing replace Cool duck sections
-        let parsed_md = Self::cool_duck_replacement(&file).await;
-        let parser = Parser::new_ext(parsed_md.as_str(), Options::all());
+        let front_matter = FrontMatter::split(&path, &file)?;
+        let metadata = PostMetadata::new(front_matter.parse(&path)?, &path, front_matter.body);
+        // Before Parsing replace shortcodes like Cool duck sections
+        let parsed_md = shortcodes::render(front_matter.body, front_matter.body_line())
+            .wrap_err_with(|| format!("Could not render {path}"))?;
+        let mut events = Parser::new_ext(parsed_md.as_str(), Options::all()).collect::<Vec<_>>();
+        let headings = markdown::assign_heading_ids(&mut events);
+        let toc = markdown::table_of_contents(&headings);
+        let config = SiteConfig::get();
+        let events = markdown::apply_theme(events, &config.theme, config.highlighting);
         let mut html = String::new();
-        html::push_html(&mut html, parser);
-        // TODO: There has to be some nicer way. Maybe this can be done in the markdown parser
-        html = info_span!("Postprocessing").in_scope(|| {
-            html.replace("<ul>", "<ul class=\"list-disc pl-5 pb-2\">")
-                .replace("<a ", "<a class=\"text-green-500\"")
-                .replace(
-                    "<code class=\"",
-                    "<code class=\"whitespace-pre-wrap scrollable overflow-x-auto pb-2 ",
-                )
-                // Make headers bigger, add padding below
-                .replace("<h1", "<h1 class=\"text-4xl font-bold pb-2\"")
-                .replace("<h2", "<h2 class=\"text-3xl font-bold pb-2\"")
-                // Center Images using tailwindcss
-                .replace("<img src", "<img class=\"mx-auto\" src")
-        });
+        html::push_html(&mut html, events.into_iter());
         Ok(Post {
             // TODO: This could probably be done better
             content: html,
             path: metadata.url.clone(),
             metadata,
+            headings,
+            toc,
         })
     }
     /// Extract images out of markdown text
@@ -202,52 +330,27 @@ impl Post {
             .map(|x| Image(x[1].to_string()))
             .collect()
     }
-    #[instrument]
-    async fn cool_duck_replacement(text: &str) -> String {
-        // Match with regex(only linux newline because im not insane) and then parse with liquid
-        let re = Regex::new(r"%Coolduck says%\s*((.|\n)*?)\s*%coolduck%").unwrap();
-        let template = liquid_parse("duck.liquid").await;
-        let result = re.replace_all(text, {
-            // Render template with $1
-            |caps: &regex::Captures| {
-                template
-                    .render(&liquid::object!({ "text": caps.get(1).unwrap().as_str(),
-                    }))
-                    .unwrap()
-            }
-        });
-        result.to_string()
-    }
     #[instrument(err)]
     pub async fn parse_all_posts() -> Result<Vec<Self>> {
         // List all files in content/posts
-        let files = std::fs::read_dir("content/posts").unwrap_or_else(|f| {
+        let posts_dir = SiteConfig::get().content_path("posts");
+        let files = std::fs::read_dir(&posts_dir).unwrap_or_else(|f| {
             panic!(
                 "Could not read posts directory: {} in directory {}",
                 f,
                 std::env::current_dir().unwrap().display()
             )
         });
-        let mut posts_list = Vec::new();
+        let mut sources = Vec::new();
         for file in files {
             let post = file.unwrap();
-            posts_list.push(
-                Post::load(format!(
-                    "content/posts/{}",
-                    post.file_name().into_string().unwrap()
-                ))
-                .await?,
-            );
-        }
-        if cfg!(not(debug_assertions)) {
-            posts_list.retain(|post| !post.metadata.draft);
+            let path = format!("{posts_dir}/{}", post.file_name().into_string().unwrap());
+            sources.push((path.clone(), Post::load(path).await?));
         }
-        let _ = POSTS.set(HashMap::from_iter(
-            posts_list
-                .iter()
-                .map(|post| (post.metadata.url.clone(), post.clone())),
-        ));
-        posts_list.sort_by(|a, b| b.metadata.date.cmp(&a.metadata.date));
+        let store = PostStore::new(sources);
+        let mut posts_list = store.posts().cloned().collect::<Vec<Post>>();
+        PostStore::replace(store);
+        posts_list.sort_by_key(|post| std::cmp::Reverse(post.metadata.date));
         Ok(posts_list)
     }
 }
@@ -274,6 +377,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_front_matter() {
+        let yaml = "---\ntitle: Ducks\n---\nIntro\n\n---\n\nOutro\n";
+        let front_matter = FrontMatter::split("duck.md", yaml).unwrap();
+        assert_eq!(front_matter.format, FrontMatterFormat::Yaml);
+        assert_eq!(front_matter.body, "Intro\n\n---\n\nOutro\n");
+        assert_eq!(front_matter.body_line(), 4);
+
+        let toml = "+++\ntitle = \"Ducks\"\ndate = \"2021-09-14\"\nurl = \"ducks\"\n+++\nQuack";
+        let front_matter = FrontMatter::split("duck.md", toml).unwrap();
+        let builder: PostMetadataBuilder = front_matter.parse("duck.md").unwrap();
+        assert_eq!(builder.title, "Ducks");
+        assert_eq!(front_matter.body, "Quack");
+
+        let error = FrontMatter::split("duck.md", "# No metadata").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("duck.md:1: missing front matter"));
+        let error = FrontMatter::split("duck.md", "---\ntitle: Ducks\n").unwrap_err();
+        assert!(error.to_string().contains("never closed"));
+        let error = FrontMatter::split("duck.md", "+++\ntitle = \"Ducks\"\ndate = 2021-\n+++\n")
+            .unwrap()
+            .parse::<PostMetadataBuilder>("duck.md")
+            .unwrap_err();
+        assert!(error.to_string().starts_with("duck.md:3: invalid TOML"));
+        let error = FrontMatter::split("duck.md", "---\ntitle: Ducks\ndate: [\n---\n")
+            .unwrap()
+            .parse::<PostMetadataBuilder>("duck.md")
+            .unwrap_err();
+        assert!(error.to_string().starts_with("duck.md:3: invalid YAML"));
+
+        // Missing keys point into the front matter instead of the opening line
+        for post in [
+            "---\ndate: 2021-09-14\n---\n",
+            "+++\ndate = \"2021-09-14\"\n+++\n",
+        ] {
+            let error = FrontMatter::split("duck.md", post)
+                .unwrap()
+                .parse::<PostMetadataBuilder>("duck.md")
+                .unwrap_err();
+            assert!(error.to_string().starts_with("duck.md:2:"), "{error}");
+            assert!(
+                error.to_string().contains("missing field `title`"),
+                "{error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_url_from_path() {
+        assert_eq!(
+            url_from_path("content/old_posts/bitboard-rust-2/index.md"),
+            "/posts/bitboard-rust-2"
+        );
+        assert_eq!(
+            url_from_path("content/posts/The Game State.md"),
+            "/posts/the-game-state"
+        );
+        // Hugo posts only have TOML front matter without a url
+        let post = Post::load("content/old_posts/bitboard-rust-2/index.md".to_string())
+            .await
+            .unwrap();
+        assert_eq!(post.path, "/posts/bitboard-rust-2");
+        assert_eq!(
+            post.metadata.title,
+            "Writing a BitBoard in Rust Pt. 2: The Game State"
+        );
+    }
+
     #[tokio::test]
     async fn test_load_all_posts() {
         let posts = Post::parse_all_posts().await.unwrap();
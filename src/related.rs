@@ -0,0 +1,125 @@
+//! Finds the posts most similar to each post by shared tags and the words they use
+use crate::post::Post;
+use crate::search::{strip_html, tokenize};
+use std::collections::{HashMap, HashSet};
+
+/// How much shared tags count compared to similar text, the rest is text similarity
+const TAG_SHARE: f64 = 0.5;
+
+/// The urls of the up to `count` most related posts for the url of every post, best first
+pub fn related_posts(posts: &[&Post], count: usize) -> HashMap<String, Vec<String>> {
+    let tags = posts
+        .iter()
+        .map(|post| {
+            post.metadata
+                .tags
+                .iter()
+                .map(|tag| tag.0.as_str())
+                .collect()
+        })
+        .collect::<Vec<HashSet<&str>>>();
+    let vectors = term_vectors(posts);
+
+    let mut related = HashMap::new();
+    for (i, post) in posts.iter().enumerate() {
+        let mut scores = (0..posts.len())
+            .filter(|j| *j != i)
+            .map(|j| {
+                let tag_score = jaccard(&tags[i], &tags[j]);
+                let text_score = cosine(&vectors[i], &vectors[j]);
+                (j, TAG_SHARE * tag_score + (1.0 - TAG_SHARE) * text_score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        related.insert(
+            post.path.clone(),
+            scores
+                .into_iter()
+                .take(count)
+                .map(|(j, _)| posts[j].path.clone())
+                .collect(),
+        );
+    }
+    related
+}
+
+/// TF-IDF weights of the words of every post, normalized to a length of 1
+fn term_vectors(posts: &[&Post]) -> Vec<HashMap<String, f64>> {
+    let frequencies = posts
+        .iter()
+        .map(|post| {
+            let text = format!(
+                "{} {} {}",
+                post.metadata.title,
+                post.metadata.description,
+                strip_html(&post.content)
+            );
+            let mut frequencies: HashMap<String, f64> = HashMap::new();
+            for term in tokenize(&text) {
+                *frequencies.entry(term).or_default() += 1.0;
+            }
+            frequencies
+        })
+        .collect::<Vec<_>>();
+    let mut documents: HashMap<&str, usize> = HashMap::new();
+    for terms in &frequencies {
+        for term in terms.keys() {
+            *documents.entry(term).or_default() += 1;
+        }
+    }
+    frequencies
+        .iter()
+        .map(|terms| {
+            // Words in every post, like "the", get a weight of 0
+            let mut vector = terms
+                .iter()
+                .map(|(term, frequency)| {
+                    let idf = (posts.len() as f64 / documents[term.as_str()] as f64).ln();
+                    (term.clone(), frequency * idf)
+                })
+                .filter(|(_, weight)| *weight > 0.0)
+                .collect::<HashMap<_, _>>();
+            let length = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+            if length > 0.0 {
+                vector.values_mut().for_each(|w| *w /= length);
+            }
+            vector
+        })
+        .collect()
+}
+
+fn jaccard(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+fn cosine(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let (small, large) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    small
+        .iter()
+        .filter_map(|(term, weight)| Some(weight * large.get(term)?))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_related_posts() {
+        let posts = Post::parse_all_posts().await.unwrap();
+        let posts = posts.iter().collect::<Vec<_>>();
+        let related = related_posts(&posts, 3);
+
+        let bitboard = &related["/posts/bitboard-rust"];
+        assert!(bitboard.len() <= 3);
+        assert!(!bitboard.contains(&"/posts/bitboard-rust".to_string()));
+        // The second part of the series shares the most tags and words
+        let first = posts.iter().find(|post| post.path == bitboard[0]).unwrap();
+        assert!(first.metadata.tags.iter().any(|tag| tag.0 == "bitboard"));
+    }
+}
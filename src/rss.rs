@@ -1,40 +1,63 @@
+use crate::cache::feed_response;
+use crate::config::SiteConfig;
 use crate::post::Post;
-use axum::http::{header, StatusCode};
-use axum::response::IntoResponse;
+use crate::utils::{feed_title, tag_path};
+use axum::extract::Path;
+use axum::http::HeaderMap;
+use axum::response::Response;
 use rss::{ChannelBuilder, Item};
 use tracing::instrument;
 
-#[instrument]
-async fn build_rss_feed() -> rss::Channel {
-    let posts = Post::parse_all_posts().await.unwrap();
+/// Builds the feed from `posts`, which are expected newest first and tagged with `tag` if given
+#[instrument(skip(posts))]
+fn build_rss_feed(posts: &[&Post], tag: Option<&str>) -> rss::Channel {
+    let config = SiteConfig::get();
+    let link = match tag {
+        Some(tag) => config.url(tag_path(tag)),
+        None => config.base_url.clone(),
+    };
     ChannelBuilder::default()
-        .title("Nereuxofficials Blog")
-        .link("https://nereux.blog")
-        .description("A blog about Rust, Linux, and other things.")
-        .language(Some("en-US".into()))
+        .title(feed_title(tag))
+        .link(link)
+        .description(config.description.clone())
+        .language(Some(config.language.clone()))
         .items(
             posts
                 .iter()
-                .map(|x| x.clone().into())
+                .map(|x| (*x).clone().into())
                 .collect::<Vec<Item>>(),
         )
         .build()
 }
 
-pub async fn serve_rss_feed() -> impl IntoResponse {
-    let feed = build_rss_feed().await;
-    let mut buffer = Vec::new();
-    feed.pretty_write_to(&mut buffer, b' ', 2).unwrap();
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
-        buffer,
+pub async fn serve_rss_feed(headers: HeaderMap) -> Response {
+    rss_response(None, &headers)
+}
+
+pub async fn serve_tag_rss_feed(Path(tag): Path<String>, headers: HeaderMap) -> Response {
+    rss_response(Some(&tag), &headers)
+}
+
+fn rss_response(tag: Option<&str>, headers: &HeaderMap) -> Response {
+    feed_response(
+        tag,
+        "feed.xml",
+        "application/rss+xml; charset=utf-8",
+        headers,
+        |posts| {
+            let mut buffer = Vec::new();
+            build_rss_feed(posts, tag)
+                .pretty_write_to(&mut buffer, b' ', 2)
+                .unwrap();
+            buffer
+        },
     )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::PostStore;
     use axum::{routing::get, Router};
     use tokio::net::TcpListener;
 
@@ -42,7 +65,7 @@ mod tests {
     async fn test_rss_feed() {
         let _ = rustls::crypto::ring::default_provider().install_default();
         let posts = Post::parse_all_posts().await.unwrap();
-        let feed = build_rss_feed().await;
+        let feed = build_rss_feed(&posts.iter().collect::<Vec<_>>(), None);
 
         assert!(!feed.items().is_empty());
         if cfg!(not(debug_assertions)) {
@@ -54,11 +77,19 @@ mod tests {
             assert!(item.title().is_some());
             assert!(item.link().is_some());
             if let Some(link) = item.link() {
-                assert!(link.starts_with("https://nereux.blog/posts/"));
+                assert!(link.starts_with(&SiteConfig::get().url("/posts/")));
                 assert!(!link.contains("//posts/"));
             }
         }
 
+        let tagged = build_rss_feed(
+            &PostStore::current().newest_first(Some("rust")),
+            Some("rust"),
+        );
+        assert!(!tagged.items().is_empty());
+        assert!(tagged.items().len() < feed.items().len());
+        assert!(tagged.title().ends_with("#rust"));
+
         let app = Router::new().route("/feed.xml", get(serve_rss_feed));
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -88,8 +119,9 @@ mod tests {
         dbg!(&body);
         assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>"));
         assert!(body.contains("<channel>"));
-        assert!(body.contains("<title>Nereuxofficials Blog</title>"));
-        assert!(body.contains("<link>https://nereux.blog</link>"));
+        let config = SiteConfig::get();
+        assert!(body.contains(&format!("<title>{}</title>", config.title)));
+        assert!(body.contains(&format!("<link>{}</link>", config.base_url)));
         assert!(!body.contains("/posts//posts/"));
         assert!(body.contains("<pubDate>"));
         assert!(body.contains(", "));
@@ -0,0 +1,191 @@
+//! Full-text search over the published posts
+//!
+//! Titles, tags, descriptions and the rendered text are split into lowercase terms. Every term
+//! points to the posts containing it, weighted by where it occurs, so a match in the title counts
+//! more than one somewhere in the text.
+use crate::post::Post;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+const TITLE_WEIGHT: u32 = 10;
+const TAG_WEIGHT: u32 = 5;
+const DESCRIPTION_WEIGHT: u32 = 3;
+const TEXT_WEIGHT: u32 = 1;
+
+/// What the browser needs to show a result
+#[derive(Debug, Serialize)]
+struct SearchDoc {
+    url: String,
+    title: String,
+    description: String,
+    date: String,
+}
+
+/// Serialized as the index for searching in the browser, so it is kept short
+#[derive(Debug, Default, Serialize)]
+pub struct SearchIndex {
+    /// The indexed posts, terms refer to them by position
+    docs: Vec<SearchDoc>,
+    /// Sorted, so terms starting with a query can be found by a range
+    terms: BTreeMap<String, Vec<(usize, u32)>>,
+}
+
+impl SearchIndex {
+    pub fn new<'a>(posts: impl IntoIterator<Item = &'a Post>) -> Self {
+        let mut index = SearchIndex::default();
+        for post in posts {
+            let doc = index.docs.len();
+            let metadata = &post.metadata;
+            index.docs.push(SearchDoc {
+                url: post.path.clone(),
+                title: metadata.title.clone(),
+                description: metadata.description.clone(),
+                date: metadata.date.to_string(),
+            });
+            let mut weights: HashMap<String, u32> = HashMap::new();
+            for (text, weight) in [
+                (metadata.title.as_str(), TITLE_WEIGHT),
+                (metadata.description.as_str(), DESCRIPTION_WEIGHT),
+            ] {
+                for term in tokenize(text) {
+                    *weights.entry(term).or_default() += weight;
+                }
+            }
+            // Tags like `C` are single letters, so none of their words are dropped
+            for tag in &metadata.tags {
+                for term in words(&tag.0) {
+                    *weights.entry(term).or_default() += TAG_WEIGHT;
+                }
+            }
+            for term in tokenize(&strip_html(&post.content)) {
+                *weights.entry(term).or_default() += TEXT_WEIGHT;
+            }
+            for (term, weight) in weights {
+                index.terms.entry(term).or_default().push((doc, weight));
+            }
+        }
+        index
+    }
+
+    /// Urls of the posts containing every term of `query`, best match first. The terms may also
+    /// be the beginning of a word, so results show up while typing.
+    pub fn search(&self, query: &str) -> Vec<&str> {
+        let mut scores: Option<HashMap<usize, f64>> = None;
+        for query_term in words(query) {
+            let mut term_scores: HashMap<usize, f64> = HashMap::new();
+            for (term, postings) in self
+                .terms
+                .range(query_term.clone()..)
+                .take_while(|(term, _)| term.starts_with(&query_term))
+            {
+                // Rare terms say more about a post than ones that are everywhere
+                let idf = (1.0 + self.docs.len() as f64 / postings.len() as f64).ln();
+                let exact = if *term == query_term { 1.0 } else { 0.5 };
+                for (doc, weight) in postings {
+                    *term_scores.entry(*doc).or_default() += *weight as f64 * idf * exact;
+                }
+            }
+            scores = Some(match scores {
+                None => term_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(doc, score)| Some((doc, score + term_scores.get(&doc)?)))
+                    .collect(),
+            });
+        }
+        let mut results = scores.unwrap_or_default().into_iter().collect::<Vec<_>>();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+            .into_iter()
+            .map(|(doc, _)| self.docs[doc].url.as_str())
+            .collect()
+    }
+}
+
+/// Lowercase words of at least two characters
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    words(text).filter(|word| word.chars().count() > 1)
+}
+
+/// All lowercase words, including single letters
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The text of rendered html without tags and entities like `&amp;`
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut in_entity = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if in_tag => {}
+            '&' => {
+                in_entity = true;
+                text.push(' ');
+            }
+            ';' if in_entity => in_entity = false,
+            c if in_entity && (c.is_alphanumeric() || c == '#') => {}
+            c => {
+                in_entity = false;
+                text.push(c);
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_search() {
+        let posts = Post::parse_all_posts().await.unwrap();
+        let index = SearchIndex::new(&posts);
+
+        let results = index.search("BitBoard");
+        assert!(results.contains(&"/posts/bitboard-rust"));
+        // Prefixes match as well
+        assert!(index.search("bitbo").contains(&"/posts/bitboard-rust"));
+        // Every term has to match
+        assert!(index.search("bitboard esp32").len() < results.len());
+        assert!(index.search("").is_empty());
+        assert!(index.search("quackquackquack").is_empty());
+    }
+
+    #[test]
+    fn test_single_letter_tag() {
+        let post = |path: &str, tag: &str| Post {
+            content: "<p>Pointers and a duck</p>".to_string(),
+            path: path.to_string(),
+            metadata: crate::post::PostMetadata {
+                tags: vec![crate::post::Tag(tag.to_string())],
+                ..Default::default()
+            },
+            headings: Vec::new(),
+            toc: Vec::new(),
+        };
+        let posts = [post("/posts/c", "C"), post("/posts/rust", "Rust")];
+        let index = SearchIndex::new(&posts);
+        assert_eq!(index.search("C"), ["/posts/c"]);
+    }
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(
+            tokenize(&strip_html(
+                "<p>Hello <b>duck</b>s &amp; &#39;geese&#39;</p>"
+            ))
+            .collect::<Vec<_>>(),
+            ["hello", "duck", "geese"]
+        );
+    }
+}
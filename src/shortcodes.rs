@@ -0,0 +1,208 @@
+//! Shortcodes embed a template from `liquid/shortcodes/` into a post:
+//!
+//! ```text
+//! {{< callout kind="warning" >}}Don't feed the ducks{{< /callout >}}
+//! {{< video src="/images/duck.mp4" />}}
+//! ```
+//!
+//! Named arguments become variables of the template and the text between the tags is `body`.
+//! `%Coolduck says% ... %coolduck%` is kept as an alias for the `coolduck` shortcode.
+use crate::templates::Templates;
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use liquid::model::Value;
+use liquid::Object;
+use pulldown_cmark::{Event, Options, Parser, Tag as MdTag};
+use regex::{Captures, Regex};
+use std::ops::Range;
+
+/// Folder below [`crate::templates::TEMPLATE_DIR`] with one template per shortcode
+pub const SHORTCODE_DIR: &str = "shortcodes";
+
+/// Replaces every shortcode in `markdown` with its rendered template.
+/// `first_line` is the line of the file the text starts on, so errors point to the right line
+pub fn render(markdown: &str, first_line: usize) -> Result<String> {
+    let markdown = cool_duck_alias(markdown);
+    let shortcodes = Shortcodes {
+        markdown: &markdown,
+        code: code_ranges(&markdown),
+        first_line,
+        tag: Regex::new(r#"\{\{<\s*(/)?\s*([\w-]+)((?:\s+[\w-]+\s*=\s*"[^"]*")*)\s*(/?)>\}\}"#)
+            .unwrap(),
+        argument: Regex::new(r#"([\w-]+)\s*=\s*"([^"]*)""#).unwrap(),
+    };
+    shortcodes.expand(0..markdown.len())
+}
+
+struct Shortcodes<'a> {
+    markdown: &'a str,
+    /// Code blocks and inline code, shortcodes in them are shown as written
+    code: Vec<Range<usize>>,
+    first_line: usize,
+    /// Opening (`{{< name k="v" >}}`), self closing (`{{< name />}}`) and closing (`{{< /name >}}`) tags
+    tag: Regex,
+    argument: Regex,
+}
+
+impl<'a> Shortcodes<'a> {
+    /// Renders the shortcodes in `range` of the markdown, including nested ones
+    fn expand(&self, range: Range<usize>) -> Result<String> {
+        let mut html = String::with_capacity(range.len());
+        let mut position = range.start;
+        while let Some(caps) = self.next_tag(position, range.end) {
+            let tag = caps.get(0).unwrap();
+            let name = &caps[2];
+            let line = self.line(tag.start());
+            if caps.get(1).is_some() {
+                bail!("Shortcode {name} on line {line} is closed but was never opened");
+            }
+            html.push_str(&self.markdown[position..tag.start()]);
+            position = tag.end();
+
+            let mut globals = Object::new();
+            for arg in self.argument.captures_iter(&caps[3]) {
+                globals.insert(arg[1].to_string().into(), Value::scalar(arg[2].to_string()));
+            }
+            if caps[4].is_empty() {
+                let Some(close) = self.closing_tag(name, position, range.end) else {
+                    bail!(
+                        "Shortcode {name} on line {line} is not closed, end it with {{{{< /{name} >}}}} \
+                         or write {{{{< {name} />}}}}"
+                    );
+                };
+                // Shortcodes may be nested in the body of another one
+                let body = &self.markdown[position..close.start];
+                let start = position + body.len() - body.trim_start().len();
+                let end = position + body.trim_end().len();
+                globals.insert(
+                    "body".into(),
+                    Value::scalar(self.expand(start..end.max(start))?),
+                );
+                position = close.end;
+            }
+
+            let template = Templates::get(&format!("{SHORTCODE_DIR}/{name}.liquid"))
+                .map_err(|_| eyre!("Unknown shortcode {name} on line {line}"))?;
+            let rendered = template
+                .render(&globals)
+                .map_err(|e| eyre!("Could not render shortcode {name} on line {line}: {e}"))?;
+            html.push_str(&rendered);
+        }
+        html.push_str(&self.markdown[position..range.end]);
+        Ok(html)
+    }
+
+    /// First tag between `from` and `to` that isn't inside code
+    fn next_tag(&self, mut from: usize, to: usize) -> Option<Captures<'a>> {
+        while let Some(caps) = self.tag.captures_at(&self.markdown[..to], from) {
+            let tag = caps.get(0).unwrap();
+            if !self.code.iter().any(|code| code.contains(&tag.start())) {
+                return Some(caps);
+            }
+            from = tag.end();
+        }
+        None
+    }
+
+    /// The tag closing `name`, skipping pairs of the same shortcode nested inside it
+    fn closing_tag(&self, name: &str, mut from: usize, to: usize) -> Option<Range<usize>> {
+        let mut depth = 0;
+        while let Some(caps) = self.next_tag(from, to) {
+            let tag = caps.get(0).unwrap();
+            from = tag.end();
+            if &caps[2] != name || !caps[4].is_empty() {
+                continue;
+            }
+            if caps.get(1).is_none() {
+                depth += 1;
+            } else if depth == 0 {
+                return Some(tag.range());
+            } else {
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.markdown[..offset].matches('\n').count() + self.first_line
+    }
+}
+
+/// Byte ranges of fenced or indented code blocks and inline code
+fn code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, Options::all())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Code(_) | Event::Start(MdTag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// Rewrites the original Cool Duck syntax to the `coolduck` shortcode, keeping newlines so
+/// line numbers in errors still match
+fn cool_duck_alias(markdown: &str) -> String {
+    let code = code_ranges(markdown);
+    // Only linux newlines because im not insane
+    let re = Regex::new(r"%Coolduck says%(\s*)((?:.|\n)*?)(\s*)%coolduck%").unwrap();
+    re.replace_all(markdown, |caps: &Captures| {
+        if code
+            .iter()
+            .any(|code| code.contains(&caps.get(0).unwrap().start()))
+        {
+            caps[0].to_string()
+        } else {
+            format!(
+                "{{{{< coolduck >}}}}{}{}{}{{{{< /coolduck >}}}}",
+                &caps[1], &caps[2], &caps[3]
+            )
+        }
+    })
+    .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_shortcodes() {
+        let html = render(
+            "Before\n\n{{< callout kind=\"warning\" title=\"Careful\" >}}\nDon't feed the ducks\n{{< /callout >}}\n\nAfter",
+            1,
+        )
+        .unwrap();
+        assert!(html.starts_with("Before\n\n"));
+        assert!(html.contains("border-yellow-500"));
+        assert!(html.contains("<p class=\"font-light\">Don't feed the ducks</p>"));
+        assert!(html.ends_with("\n\nAfter"));
+
+        let html = render("%Coolduck says%\nQuack\n%coolduck%", 1).unwrap();
+        assert!(html.contains("Cool Duck says"));
+        assert!(html.contains("Quack"));
+
+        let error = render("a\n{{< callout >}} never closed", 1).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(render("{{< no-such-shortcode />}}", 1).is_err());
+    }
+
+    #[test]
+    fn test_code_is_not_expanded() {
+        let markdown = "Use `{{< callout >}}`:\n\n```\n{{< callout >}}Hi{{< /callout >}}\n```\n";
+        assert_eq!(render(markdown, 1).unwrap(), markdown);
+    }
+
+    #[test]
+    fn test_nested_shortcodes() {
+        let html = render(
+            "{{< coolduck >}}\nOuter\n{{< coolduck >}}Inner{{< /coolduck >}}\nAfter\n{{< /coolduck >}}",
+            1,
+        )
+        .unwrap();
+        assert_eq!(html.matches("Cool Duck says").count(), 2);
+        assert!(!html.contains("{{<"));
+
+        // Errors inside a body point to the line in the whole file
+        let error = render("{{< coolduck >}}\n\n{{< nope />}}\n{{< /coolduck >}}", 5).unwrap_err();
+        assert!(error.to_string().contains("line 7"), "{error}");
+    }
+}
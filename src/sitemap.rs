@@ -0,0 +1,135 @@
+//! `/sitemap.xml` listing every published page and `/robots.txt` pointing crawlers to it
+use crate::config::SiteConfig;
+use crate::store::{PostStore, TagSort};
+use crate::utils::tag_path;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use pulldown_cmark_escape::escape_html;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+/// Rules for `/robots.txt`, set as `[robots]` in duckblog.toml
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Robots {
+    /// Crawlers the rules apply to
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Paths crawlers should not visit
+    #[serde(default)]
+    pub disallow: Vec<String>,
+}
+
+fn default_user_agent() -> String {
+    "*".to_string()
+}
+
+impl Default for Robots {
+    fn default() -> Self {
+        Robots {
+            user_agent: default_user_agent(),
+            disallow: Vec::new(),
+        }
+    }
+}
+
+/// Pages that aren't posts, tags or series. `build` exports exactly these, so the sitemap of a
+/// static site has no dead links. They get the date of the newest post as `lastmod`
+pub const STATIC_PAGES: &[&str] = &["/", "/posts", "/tags", "/archive", "/about"];
+
+#[instrument(skip(store))]
+fn build_sitemap(store: &PostStore) -> String {
+    let config = SiteConfig::get();
+    let latest = store.posts().map(|post| post.metadata.date).max();
+    let mut posts = store.posts().collect::<Vec<_>>();
+    posts.sort_by_key(|post| std::cmp::Reverse(post.metadata.date));
+
+    let pages = STATIC_PAGES
+        .iter()
+        .map(|page| (page.to_string(), latest))
+        // Posts are served with a trailing slash, everything else redirects
+        .chain(
+            posts
+                .iter()
+                .map(|post| (format!("{}/", post.path), Some(post.metadata.date))),
+        )
+        .chain(store.all_series().map(|series| (series.url.clone(), None)))
+        .chain(
+            store
+                .tags(TagSort::Name)
+                .into_iter()
+                .map(|tag| (tag_path(&tag.name), Some(tag.latest))),
+        );
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (path, lastmod) in pages {
+        xml.push_str("  <url>\n    <loc>");
+        escape_html(&mut xml, &config.url(path)).unwrap();
+        xml.push_str("</loc>\n");
+        if let Some(lastmod) = lastmod {
+            xml.push_str(&format!("    <lastmod>{lastmod}</lastmod>\n"));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn build_robots_txt() -> String {
+    let config = SiteConfig::get();
+    let mut robots = format!("User-agent: {}\n", config.robots.user_agent);
+    if config.robots.disallow.is_empty() {
+        robots.push_str("Disallow:\n");
+    }
+    for path in &config.robots.disallow {
+        robots.push_str(&format!("Disallow: {path}\n"));
+    }
+    robots.push_str(&format!("\nSitemap: {}\n", config.url("/sitemap.xml")));
+    robots
+}
+
+pub async fn serve_sitemap() -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        build_sitemap(&PostStore::current()),
+    )
+}
+
+pub async fn serve_robots_txt() -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        build_robots_txt(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::Tag;
+    use crate::store::test_store;
+
+    #[tokio::test]
+    async fn test_sitemap() {
+        let file = "content/posts/The Basics.md";
+        let store = test_store(&[file]).await;
+        let mut post = store.get("/posts/bitboard-rust").unwrap().clone();
+        post.metadata.tags.push(Tag("Entwürfe & Ideen".to_string()));
+        let store = store.with_file(file, Some(post.clone()));
+        let sitemap = build_sitemap(&store);
+        let config = SiteConfig::get();
+
+        assert!(sitemap.contains(&format!("<loc>{}/</loc>", config.base_url)));
+        assert!(sitemap.contains(&format!("<loc>{}/</loc>", config.url(&post.path))));
+        let tag = &post.metadata.tags[0];
+        assert!(sitemap.contains(&format!("<loc>{}</loc>", tag.get_url())));
+        assert!(sitemap.contains("/tags/Entw%C3%BCrfe%20&amp;%20Ideen</loc>"));
+        assert!(!sitemap.contains("/donate"));
+        assert!(sitemap.contains(&format!("<lastmod>{}</lastmod>", post.metadata.date)));
+
+        assert!(build_robots_txt().contains(&format!("Sitemap: {}", config.url("/sitemap.xml"))));
+    }
+}
@@ -1,124 +1,274 @@
-//! Since we didn't do it in the first place we need to build a static site from the running server
+//! Builds a static version of the site by rendering every route through the [`router`] in process
+use crate::config::SiteConfig;
+use crate::pagination::{page_count, page_url};
 use crate::post::Post;
-use axum::http::Uri;
+use crate::router;
+use crate::sitemap::STATIC_PAGES;
+use crate::store::PostStore;
+use crate::utils::tag_path;
+use axum::body::{to_bytes, Body, Bytes};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::Result;
 use copy_dir::copy_dir;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use itertools::Itertools;
-use std::process::exit;
-use std::str::FromStr;
+use percent_encoding::percent_decode_str;
+use std::io::Write;
+use std::path::Path;
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
-use tracing::{debug, info, warn};
-// TODO: Fix paths
-// Also: Fuck paths
+use tower::ServiceExt;
+use tracing::{debug, info, instrument, warn};
 
-// Has to end with a /
-const SERVER_URL: &str = "127.0.0.1:8010/";
-const FOLDER: &str = "public/";
-pub async fn generate_static_site() {
-    // Delete previous files
-    let _ = fs::remove_dir_all(FOLDER).await;
+/// Output folder used when none is given on the command line
+pub const DEFAULT_OUT: &str = "public";
+
+/// Written into the output folder so a later build knows it may clear it
+const MARKER: &str = ".duckblog";
+
+/// Extensions of the files that get `.br` and `.gz` siblings, other formats are compressed already
+const COMPRESSIBLE: &[&str] = &["html", "xml", "json", "css", "js", "svg", "txt"];
+
+#[instrument(err)]
+pub async fn generate_static_site(out: &Path, force: bool) -> Result<()> {
+    clear_out_dir(out, force).await?;
     // Create our folders
-    let _ = fs::create_dir("public/").await;
-    let _ = fs::create_dir("public/tags").await;
-    let _ = fs::create_dir("public/posts").await;
-    // Wait until the site is up
-    while reqwest::get(format!("http://{SERVER_URL}/")).await.is_err() {
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    fs::create_dir_all(out.join("tags")).await?;
+    fs::create_dir_all(out.join("posts")).await?;
+    fs::write(
+        out.join(MARKER),
+        "Generated by duckblog, `build` clears this folder\n",
+    )
+    .await?;
+    let app = router();
+    let posts = Post::parse_all_posts().await?;
+    generate_posts(&app, out, &posts).await?;
+    copy_static_files(out)?;
+    generate_404(&app, out).await?;
+    generate_tags(&app, out, &posts).await?;
+    for page in STATIC_PAGES {
+        save_page_to_path(&app, out, page).await?;
+    }
+    for page in 2..=page_count(posts.len()) {
+        save_page_to_path(&app, out, &page_url(None, page)).await?;
+    }
+    save_file_to_path(&app, out, "/tags.json").await?;
+    generate_archive(&app, out).await?;
+    for series in PostStore::current().all_series() {
+        save_page_to_path(&app, out, &series.url).await?;
     }
-    generate_posts().await;
-    copy_static_files().await;
-    generate_404().await;
-    generate_tags().await;
-    save_page_to_path(Uri::from_str(format!("http://{SERVER_URL}/index.html").as_str()).unwrap())
-        .await;
-    save_page_to_path(Uri::from_str(format!("http://{SERVER_URL}/about").as_str()).unwrap()).await;
-    save_page_to_path(Uri::from_str(format!("http://{SERVER_URL}/posts").as_str()).unwrap()).await;
-    // Rss feed
-    save_page_to_path(Uri::from_str(format!("http://{SERVER_URL}/feed.xml").as_str()).unwrap())
-        .await;
-    copy_post_images().await;
+    save_page_to_path(&app, out, "/search").await?;
+    save_file_to_path(&app, out, "/search.json").await?;
+    // Rss, Atom and JSON feeds
+    save_file_to_path(&app, out, "/feed.xml").await?;
+    save_file_to_path(&app, out, "/atom.xml").await?;
+    save_file_to_path(&app, out, "/feed.json").await?;
+    save_file_to_path(&app, out, "/sitemap.xml").await?;
+    save_file_to_path(&app, out, "/robots.txt").await?;
+    copy_post_images(out, &posts).await;
+    precompress(out)?;
     info!("Static site generated");
-    exit(0);
+    Ok(())
+}
+/// Deletes the output of a previous build. Folders that weren't created by duckblog
+/// are only cleared with `force`, so a wrong `--out` can't wipe unrelated files
+async fn clear_out_dir(out: &Path, force: bool) -> Result<()> {
+    let mut entries = match fs::read_dir(out).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", out.display())),
+    };
+    if entries.next_entry().await?.is_none() {
+        return Ok(());
+    }
+    if !force && !is_previous_build(out).await? {
+        bail!(
+            "{} is not empty and is not a previous duckblog build (no {MARKER} file, nor \
+             index.html and static/), pass --force to delete its contents",
+            out.display()
+        );
+    }
+    fs::remove_dir_all(out)
+        .await
+        .wrap_err_with(|| format!("Could not delete the previous build in {}", out.display()))
+}
+
+/// Builds before the marker was introduced are recognised by their index page and static folder
+async fn is_previous_build(out: &Path) -> Result<bool> {
+    if fs::try_exists(out.join(MARKER)).await? {
+        return Ok(true);
+    }
+    let has_index = fs::metadata(out.join("index.html"))
+        .await
+        .is_ok_and(|meta| meta.is_file());
+    let has_static = fs::metadata(out.join("static"))
+        .await
+        .is_ok_and(|meta| meta.is_dir());
+    Ok(has_index && has_static)
 }
-async fn copy_post_images() {
+
+async fn copy_post_images(out: &Path, posts: &[Post]) {
     info!("Copying post images");
-    let posts = Post::parse_all_posts().await.unwrap();
+    let config = SiteConfig::get();
     for post in posts {
-        if Post::parse_file(format!("content/{}/index", post.path))
+        if Post::parse_file(config.content_path(format!("{}/index", post.path)))
             .await
             .is_ok()
         {
-            let src = format!("content/{}images", post.path);
-            let dest = format!("{FOLDER}/{}/images", post.path);
+            let src = config.content_path(format!("{}images", post.path));
+            let dest = out.join(format!("{}/images", post.path.trim_start_matches('/')));
             let res = copy_dir(&src, &dest);
             if res.is_ok() {
-                info!("Copied images from {} to {}", &src, &dest);
+                info!("Copied images from {} to {}", &src, dest.display());
             } else {
-                warn!("Couldn't copy images from {} to {}", src, dest);
+                warn!("Couldn't copy images from {} to {}", src, dest.display());
             }
         }
     }
 }
-async fn generate_posts() {
-    // Wait until the site is up
-    while reqwest::get(format!("http://{SERVER_URL}/")).await.is_err() {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-    }
-    let posts = Post::parse_all_posts().await.unwrap();
+async fn generate_posts(app: &Router, out: &Path, posts: &[Post]) -> Result<()> {
     debug!(
         "Found posts: {}",
         posts.iter().map(|x| x.path.clone()).join(", ")
     );
     for post in posts {
-        let uri = format!("http://{SERVER_URL}{}", post.path);
-        save_page_to_path(Uri::from_str(uri.as_str()).unwrap()).await;
+        // Posts are served with a trailing slash, otherwise we get redirected
+        let page = render_page(app, &format!("{}/", post.path), StatusCode::OK).await?;
+        write_page(out, &post.path, page).await?;
     }
+    Ok(())
 }
-async fn generate_tags() {
-    let posts = Post::parse_all_posts().await.unwrap();
+async fn generate_tags(app: &Router, out: &Path, posts: &[Post]) -> Result<()> {
     let mut tags: Vec<String> = Vec::new();
     posts
         .iter()
         .map(|post| post.metadata.tags.clone())
         .for_each(|tag| tags.extend(tag.iter().map(|x| x.to_string()).collect::<Vec<String>>()));
     for tag in tags.iter().unique() {
-        let uri = format!("http://{SERVER_URL}/tags/{tag}");
-        save_page_to_path(Uri::from_str(uri.as_str()).unwrap()).await;
+        save_page_to_path(app, out, &tag_path(tag)).await?;
+        let tagged = posts
+            .iter()
+            .filter(|post| post.metadata.tags.iter().any(|t| t == tag))
+            .count();
+        for page in 2..=page_count(tagged) {
+            save_page_to_path(app, out, &page_url(Some(tag), page)).await?;
+        }
+        for feed in ["feed.xml", "atom.xml", "feed.json"] {
+            save_file_to_path(app, out, &format!("{}/{feed}", tag_path(tag))).await?;
+        }
     }
+    Ok(())
 }
-/// Save 404 page
-async fn generate_404() {
-    let uri = format!("http://{SERVER_URL}/404");
-    save_page_to_path(Uri::from_str(uri.as_str()).unwrap()).await;
-}
-/// Saves the html under the URL to the path in the URL
-async fn save_page_to_path(uri: Uri) {
-    // The url path with the first / removed
-    let mut path = uri.path();
-    if path.starts_with('/') {
-        path = &path[1..path.len()];
+async fn generate_archive(app: &Router, out: &Path) -> Result<()> {
+    for year in PostStore::current().archive() {
+        save_page_to_path(app, out, &year.url).await?;
+        for month in year.months {
+            save_page_to_path(app, out, &month.url).await?;
+        }
     }
-    let big_path = format!("{path}index");
-    if path.ends_with('/') {
-        fs::create_dir_all(format!("{FOLDER}/{path}"))
-            .await
-            .expect("Could not create dir");
-        path = big_path.as_str();
+    Ok(())
+}
+/// Save 404 page
+async fn generate_404(app: &Router, out: &Path) -> Result<()> {
+    let page = render_page(app, "/404", StatusCode::NOT_FOUND).await?;
+    write_page(out, "/404", page).await
+}
+/// Renders the page under `path` and saves it to the same path in the output folder
+async fn save_page_to_path(app: &Router, out: &Path, path: &str) -> Result<()> {
+    let page = render_page(app, path, StatusCode::OK).await?;
+    write_page(out, path, page).await
+}
+/// Like [`save_page_to_path`] for routes that serve a file like `/feed.xml`
+async fn save_file_to_path(app: &Router, out: &Path, path: &str) -> Result<()> {
+    let file = render_page(app, path, StatusCode::OK).await?;
+    write_file(out, path, file).await
+}
+/// Sends a request for `path` through the router and returns the body if the status matches
+async fn render_page(app: &Router, path: &str, expected: StatusCode) -> Result<Bytes> {
+    let request = Request::get(path)
+        .body(Body::empty())
+        .wrap_err_with(|| format!("Invalid path {path}"))?;
+    let response = app.clone().oneshot(request).await?;
+    if response.status() != expected {
+        bail!(
+            "Rendering {} returned {} instead of {}",
+            path,
+            response.status(),
+            expected
+        );
     }
-    let mut response = reqwest::get(uri.to_string().trim_end_matches('/'))
+    to_bytes(response.into_body(), usize::MAX)
         .await
-        .unwrap();
-    if path.ends_with(".html") {
-        path = &path[..path.len() - 5];
+        .map_err(|e| eyre!("Could not read body of {}: {}", path, e))
+}
+/// Writes a rendered html page to `path` with `.html` appended, `/` becomes `index.html`
+async fn write_page(out: &Path, path: &str, content: Bytes) -> Result<()> {
+    let path = path.trim_end_matches('/');
+    let path = if path.is_empty() { "/index" } else { path };
+    write_file(out, &format!("{path}.html"), content).await
+}
+/// Writes a response to the file of the same path, percent-encoded tags are decoded so a
+/// static file server finds them
+async fn write_file(out: &Path, path: &str, content: Bytes) -> Result<()> {
+    let path = percent_decode_str(path.trim_start_matches('/')).decode_utf8()?;
+    let file = out.join(path.as_ref());
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).await?;
     }
-    let mut file = tokio::fs::File::create(format!("{FOLDER}{path}.html"))
+    fs::write(&file, content)
         .await
-        .unwrap();
-    while let Some(chunk) = response.chunk().await.unwrap() {
-        file.write_all(&chunk).await.unwrap();
-    }
+        .wrap_err_with(|| format!("Could not write {}", file.display()))
 }
 /// Copy our static resources
-async fn copy_static_files() {
-    copy_dir("static", format!("{FOLDER}static")).expect("Copying files failed");
+fn copy_static_files(out: &Path) -> Result<()> {
+    let errors = copy_dir("static", out.join("static"))?;
+    if !errors.is_empty() {
+        bail!("Copying static files failed: {:?}", errors);
+    }
+    Ok(())
+}
+/// Writes brotli and gzip compressed copies next to every compressible file in `dir`, so servers
+/// can send them as they are instead of compressing on every request
+#[instrument(err)]
+fn precompress(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            precompress(&path)?;
+            continue;
+        }
+        if !path
+            .extension()
+            .is_some_and(|ext| COMPRESSIBLE.iter().any(|c| ext == *c))
+        {
+            continue;
+        }
+        let content = std::fs::read(&path)?;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&content)?;
+        write_compressed(&path, "gz", &content, &gzip.finish()?)?;
+
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        brotli.write_all(&content)?;
+        write_compressed(&path, "br", &content, &brotli.into_inner())?;
+    }
+    Ok(())
+}
+/// Saves `compressed` as `path` with `extension` appended, unless it isn't smaller than `original`
+fn write_compressed(
+    path: &Path,
+    extension: &str,
+    original: &[u8],
+    compressed: &[u8],
+) -> Result<()> {
+    if compressed.len() >= original.len() {
+        return Ok(());
+    }
+    let mut file = path.as_os_str().to_owned();
+    file.push(format!(".{extension}"));
+    std::fs::write(&file, compressed)
+        .wrap_err_with(|| format!("Could not write {}", Path::new(&file).display()))
 }
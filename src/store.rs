@@ -0,0 +1,468 @@
+//! The published posts, kept in memory and swapped as a whole whenever something changes on disk
+use crate::cache::CachedResponse;
+use crate::config::SiteConfig;
+use crate::post::Post;
+use crate::related::related_posts;
+use crate::search::SearchIndex;
+use crate::utils::{slugify, tag_path};
+use crate::POSTS;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many posts have a tag and when the newest of them was published
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagStats {
+    pub name: String,
+    /// Percent-encoded path of the tag page
+    pub url: String,
+    pub count: usize,
+    pub latest: NaiveDate,
+}
+
+/// Order of [`PostStore::tags`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSort {
+    /// Alphabetically
+    #[default]
+    Name,
+    /// Most posts first
+    Count,
+}
+
+/// Number of posts published in a year, split up by month
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub count: usize,
+    /// `/archive/2022`
+    pub url: String,
+    /// Months with posts, newest first
+    pub months: Vec<ArchiveMonth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveMonth {
+    /// 1 to 12
+    pub month: u32,
+    /// English name (`March`)
+    pub name: String,
+    pub count: usize,
+    /// `/archive/2022/03`
+    pub url: String,
+}
+
+/// Posts sharing a `series` in their front matter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Series {
+    pub name: String,
+    /// `/series/writing-a-bitboard-in-rust`
+    pub url: String,
+    /// Urls of the posts in reading order
+    posts: Vec<String>,
+}
+
+/// Where a post is in its series, passed to `post.html.liquid` as `series`
+#[derive(Debug, Serialize)]
+pub struct SeriesPosition<'a> {
+    pub name: &'a str,
+    pub url: &'a str,
+    /// Starts at 1
+    pub part: usize,
+    pub parts: usize,
+    pub prev: Option<&'a Post>,
+    pub next: Option<&'a Post>,
+}
+
+#[derive(Debug, Default)]
+pub struct PostStore {
+    /// Posts by their url (`/posts/bitboard-rust`)
+    posts: HashMap<String, Post>,
+    /// Url of the post parsed from each markdown file, used to replace or drop it on changes
+    sources: HashMap<String, String>,
+    /// Urls of all posts from oldest to newest
+    chronological: Vec<String>,
+    /// Position of every post url in `chronological`
+    positions: HashMap<String, usize>,
+    /// Series by the slug of their name
+    series: HashMap<String, Series>,
+    /// Urls of the most related posts by post url, best first
+    related: HashMap<String, Vec<String>>,
+    /// Full-text index over all posts
+    search: SearchIndex,
+    /// Newest modification of a post file or the folders containing them, `None` without posts
+    updated: Option<SystemTime>,
+    /// Responses built from these posts, e.g. feeds, by request path. Dropped with the store.
+    responses: Mutex<HashMap<String, Arc<CachedResponse>>>,
+}
+
+impl PostStore {
+    /// Builds a store from `(file, post)` pairs, indexes them for search and finds related posts.
+    /// Drafts are only kept in debug builds.
+    pub fn new(posts: impl IntoIterator<Item = (String, Post)>) -> Self {
+        let mut store = PostStore::default();
+        for (file, post) in posts {
+            if cfg!(not(debug_assertions)) && post.metadata.draft {
+                continue;
+            }
+            store.sources.insert(file, post.path.clone());
+            store.posts.insert(post.path.clone(), post);
+        }
+        let mut chronological = store.posts().collect::<Vec<_>>();
+        // Sorting by url as well keeps posts from the same day in a stable order
+        chronological.sort_by(|a, b| (a.metadata.date, &a.path).cmp(&(b.metadata.date, &b.path)));
+        store.chronological = chronological.iter().map(|post| post.path.clone()).collect();
+        store.positions = store
+            .chronological
+            .iter()
+            .enumerate()
+            .map(|(i, url)| (url.clone(), i))
+            .collect();
+        store.updated = store
+            .sources
+            .keys()
+            .flat_map(|file| {
+                [
+                    Path::new(file),
+                    Path::new(file).parent().unwrap_or(Path::new(".")),
+                ]
+            })
+            .filter_map(|path| {
+                path.metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .max();
+        let series = collect_series(&store);
+        store.series = series;
+        let posts = store.newest_first(None);
+        let related = related_posts(&posts, SiteConfig::get().related_posts);
+        let search = SearchIndex::new(posts);
+        store.related = related;
+        store.search = search;
+        store
+    }
+
+    /// A snapshot of the currently published posts. Later reloads don't affect it.
+    pub fn current() -> Arc<PostStore> {
+        Self::lock().read().unwrap().clone()
+    }
+
+    /// Atomically publishes `store` for all following requests
+    pub fn replace(store: PostStore) {
+        *Self::lock().write().unwrap() = Arc::new(store);
+    }
+
+    fn lock() -> &'static RwLock<Arc<PostStore>> {
+        POSTS.get_or_init(|| RwLock::new(Arc::default()))
+    }
+
+    /// Returns a copy of this store in which the post from `file` is replaced by `post`,
+    /// or removed if `post` is `None`
+    pub fn with_file(&self, file: &str, post: Option<Post>) -> Self {
+        let mut posts = self
+            .sources
+            .iter()
+            .filter(|(source, _)| source.as_str() != file)
+            .filter_map(|(source, url)| Some((source.clone(), self.posts.get(url)?.clone())))
+            .collect::<Vec<(String, Post)>>();
+        posts.extend(post.map(|post| (file.to_string(), post)));
+        let mut store = PostStore::new(posts);
+        // Deleting the newest post must not move `Last-Modified` of the feeds backwards
+        store.updated = store.updated.max(self.updated);
+        store
+    }
+
+    pub fn get(&self, url: &str) -> Option<&Post> {
+        self.posts.get(url)
+    }
+
+    /// When the posts last changed, used as `Last-Modified` of the feeds. Based on the files,
+    /// so restarting the server doesn't change it
+    pub fn updated(&self) -> SystemTime {
+        self.updated.unwrap_or(UNIX_EPOCH)
+    }
+
+    pub fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.posts.values()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.posts()
+            .any(|post| post.metadata.tags.iter().any(|t| t.0 == tag))
+    }
+
+    /// Returns the response cached under `key`, building it with `build` on first use
+    pub fn cached(&self, key: &str, build: impl FnOnce() -> CachedResponse) -> Arc<CachedResponse> {
+        if let Some(response) = self.responses.lock().unwrap().get(key) {
+            return response.clone();
+        }
+        // Built without holding the lock, so a slow build doesn't block other responses
+        let response = Arc::new(build());
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), response.clone());
+        response
+    }
+
+    /// Every tag used by a post with its statistics
+    pub fn tags(&self, sort: TagSort) -> Vec<TagStats> {
+        let mut tags: HashMap<&str, TagStats> = HashMap::new();
+        for post in self.posts() {
+            for tag in &post.metadata.tags {
+                let stats = tags.entry(&tag.0).or_insert_with(|| TagStats {
+                    name: tag.0.clone(),
+                    url: tag_path(&tag.0),
+                    count: 0,
+                    latest: post.metadata.date,
+                });
+                stats.count += 1;
+                stats.latest = stats.latest.max(post.metadata.date);
+            }
+        }
+        let mut tags = tags.into_values().collect::<Vec<_>>();
+        match sort {
+            TagSort::Name => tags.sort_by(|a, b| a.name.cmp(&b.name)),
+            TagSort::Count => {
+                tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)))
+            }
+        }
+        tags
+    }
+
+    /// Years and months with posts and how many were published in each, newest first
+    pub fn archive(&self) -> Vec<ArchiveYear> {
+        let mut years: Vec<ArchiveYear> = Vec::new();
+        for post in self.newest_first(None) {
+            let date = post.metadata.date;
+            if years.last().map(|y| y.year) != Some(date.year()) {
+                years.push(ArchiveYear {
+                    year: date.year(),
+                    count: 0,
+                    url: format!("/archive/{}", date.year()),
+                    months: Vec::new(),
+                });
+            }
+            let year = years.last_mut().unwrap();
+            year.count += 1;
+            if year.months.last().map(|m| m.month) != Some(date.month()) {
+                year.months.push(ArchiveMonth {
+                    month: date.month(),
+                    name: date.format("%B").to_string(),
+                    count: 0,
+                    url: format!("/archive/{}/{:02}", date.year(), date.month()),
+                });
+            }
+            year.months.last_mut().unwrap().count += 1;
+        }
+        years
+    }
+
+    /// The series with the slug `slug`, e.g. `writing-a-bitboard-in-rust`
+    pub fn series(&self, slug: &str) -> Option<&Series> {
+        self.series.get(slug)
+    }
+
+    pub fn all_series(&self) -> impl Iterator<Item = &Series> {
+        self.series.values()
+    }
+
+    /// The posts of `series` in reading order
+    pub fn series_posts(&self, series: &Series) -> Vec<&Post> {
+        series
+            .posts
+            .iter()
+            .filter_map(|url| self.get(url))
+            .collect()
+    }
+
+    /// Part number and neighbours of `post` in its series, if it is part of one
+    pub fn series_position(&self, post: &Post) -> Option<SeriesPosition<'_>> {
+        let series = self.series(&slugify(post.metadata.series.as_ref()?))?;
+        let index = series.posts.iter().position(|url| *url == post.path)?;
+        Some(SeriesPosition {
+            name: &series.name,
+            url: &series.url,
+            part: index + 1,
+            parts: series.posts.len(),
+            prev: index
+                .checked_sub(1)
+                .and_then(|i| self.get(&series.posts[i])),
+            next: series.posts.get(index + 1).and_then(|url| self.get(url)),
+        })
+    }
+
+    /// The posts most related to the post at `url`, best first
+    pub fn related(&self, url: &str) -> Vec<&Post> {
+        self.related
+            .get(url)
+            .into_iter()
+            .flatten()
+            .filter_map(|url| self.get(url))
+            .collect()
+    }
+
+    /// Posts matching `query`, best match first
+    pub fn search(&self, query: &str) -> Vec<&Post> {
+        self.search
+            .search(query)
+            .into_iter()
+            .filter_map(|url| self.get(url))
+            .collect()
+    }
+
+    pub fn search_index(&self) -> &SearchIndex {
+        &self.search
+    }
+
+    /// Posts sorted newest first, only those tagged with `tag` if one is given
+    pub fn newest_first(&self, tag: Option<&str>) -> Vec<&Post> {
+        self.chronological
+            .iter()
+            .rev()
+            .filter_map(|url| self.get(url))
+            .filter(|post| match tag {
+                Some(tag) => post.metadata.tags.iter().any(|t| t.0 == tag),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// The posts published right before and after the post at `url`
+    pub fn neighbours(&self, url: &str) -> (Option<&Post>, Option<&Post>) {
+        let Some(&position) = self.positions.get(url) else {
+            return (None, None);
+        };
+        let previous = position
+            .checked_sub(1)
+            .and_then(|i| self.get(&self.chronological[i]));
+        let next = self
+            .chronological
+            .get(position + 1)
+            .and_then(|url| self.get(url));
+        (previous, next)
+    }
+}
+
+/// Groups the posts of `store` by their `series`, ordered by `series_order` and then date
+fn collect_series(store: &PostStore) -> HashMap<String, Series> {
+    let mut posts = store
+        .posts()
+        .filter(|post| post.metadata.series.is_some())
+        .collect::<Vec<_>>();
+    posts.sort_by_key(|post| {
+        (
+            post.metadata.series_order.unwrap_or(u32::MAX),
+            post.metadata.date,
+        )
+    });
+    let mut series: HashMap<String, Series> = HashMap::new();
+    for post in posts {
+        let name = post.metadata.series.clone().unwrap_or_default();
+        let slug = slugify(&name);
+        series
+            .entry(slug.clone())
+            .or_insert_with(|| Series {
+                name,
+                url: format!("/series/{slug}"),
+                posts: Vec::new(),
+            })
+            .posts
+            .push(post.path.clone());
+    }
+    series
+}
+
+/// Store of the posts in `files`, shared by the tests of the modules built on the store
+#[cfg(test)]
+pub async fn test_store(files: &[&str]) -> PostStore {
+    let mut sources = Vec::new();
+    for file in files {
+        sources.push((
+            file.to_string(),
+            Post::load(file.to_string()).await.unwrap(),
+        ));
+    }
+    PostStore::new(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PART_ONE: &str = "content/posts/The Basics.md";
+    const PART_TWO: &str = "content/posts/The Game State.md";
+
+    #[tokio::test]
+    async fn test_with_file() {
+        let store = test_store(&[PART_ONE]).await;
+        let post = store.get("/posts/bitboard-rust").unwrap().clone();
+
+        // Changing the url of a post drops the old one
+        let mut moved = post.clone();
+        moved.path = "/posts/bitboard-rust-moved".to_string();
+        let store = store.with_file(PART_ONE, Some(moved));
+        assert!(store.get("/posts/bitboard-rust").is_none());
+        assert!(store.get("/posts/bitboard-rust-moved").is_some());
+
+        let store = store.with_file(PART_ONE, None);
+        assert_eq!(store.posts().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tags() {
+        let store = test_store(&[PART_ONE, PART_TWO]).await;
+        let tags = store.tags(TagSort::Name);
+        assert!(tags.iter().any(|tag| tag.name == "rust" && tag.count == 2));
+        assert!(tags.windows(2).all(|pair| pair[0].name < pair[1].name));
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let store = test_store(&[PART_ONE, PART_TWO]).await;
+        let archive = store.archive();
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive[0].count, 2);
+        let months = archive[0]
+            .months
+            .iter()
+            .map(|month| (month.month, month.count));
+        assert_eq!(months.collect::<Vec<_>>(), [(9, 1), (8, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_series() {
+        let store = test_store(&[PART_ONE, PART_TWO]).await;
+        let part_two = store.get("/posts/bitboard-rust-2").unwrap();
+        let position = store.series_position(part_two).unwrap();
+        assert_eq!((position.part, position.parts), (2, 2));
+        assert_eq!(position.prev.unwrap().path, "/posts/bitboard-rust");
+        assert!(position.next.is_none());
+        let series = store.series("writing-a-bitboard-in-rust").unwrap();
+        assert_eq!(store.series_posts(series).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_neighbours() {
+        let store = test_store(&[PART_ONE, PART_TWO]).await;
+        let (previous, next) = store.neighbours("/posts/bitboard-rust");
+        assert!(previous.is_none());
+        assert_eq!(next.unwrap().path, "/posts/bitboard-rust-2");
+        assert_eq!(store.newest_first(None)[0].path, "/posts/bitboard-rust-2");
+    }
+
+    #[tokio::test]
+    async fn test_updated() {
+        let store = test_store(&[PART_ONE, PART_TWO]).await;
+        let modified = Path::new(PART_TWO).metadata().unwrap().modified().unwrap();
+        assert!(store.updated() >= modified);
+        // Removing a post never moves the time backwards
+        assert!(store.with_file(PART_TWO, None).updated() >= store.updated());
+        assert_eq!(PostStore::default().updated(), UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,186 @@
+//! All liquid templates, compiled once at startup and again whenever one of them changes.
+//!
+//! Every file is also registered as a partial under its path without the `.liquid` extension,
+//! so `{% include "navbar" %}` renders `liquid/navbar.liquid`.
+use crate::TEMPLATES;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use liquid::partials::{EagerCompiler, InMemorySource};
+use liquid::Template;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, instrument};
+
+/// Folder the templates are loaded from
+pub const TEMPLATE_DIR: &str = "liquid";
+
+pub struct Templates {
+    /// Compiled templates by their file name relative to [`TEMPLATE_DIR`] (`post.html.liquid`)
+    templates: HashMap<String, Arc<Template>>,
+}
+
+impl Templates {
+    /// Reads and compiles every `.liquid` file below `dir`
+    #[instrument(skip(dir), fields(dir = %dir.as_ref().display()), err)]
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut sources = HashMap::new();
+        read_sources(dir, dir, &mut sources)?;
+
+        let mut partials = InMemorySource::new();
+        for (name, source) in &sources {
+            partials.add(name.trim_end_matches(".liquid"), source.clone());
+        }
+        let parser = liquid::ParserBuilder::with_stdlib()
+            .partials(EagerCompiler::new(partials))
+            .build()
+            .wrap_err("Could not build liquid compiler")?;
+
+        let mut templates = HashMap::new();
+        for (name, source) in sources {
+            let template = parser
+                .parse(&source)
+                .map_err(|e| eyre!("Could not compile template {}: {}", name, e))?;
+            templates.insert(name, Arc::new(template));
+        }
+        Ok(Templates { templates })
+    }
+
+    /// Returns the compiled template for a file in [`TEMPLATE_DIR`], e.g. `post.html.liquid`
+    pub fn get(name: &str) -> Result<Arc<Template>> {
+        Self::lock()?
+            .read()
+            .unwrap()
+            .templates
+            .get(name)
+            .cloned()
+            .ok_or_else(|| eyre!("Template {} does not exist", name))
+    }
+
+    /// Compiles the templates at startup so errors show up before the first request.
+    /// Has to run before anything is rendered, including the shortcodes of posts.
+    pub fn init() -> Result<()> {
+        let templates = Templates::load(TEMPLATE_DIR)?;
+        TEMPLATES
+            .set(RwLock::new(Arc::new(templates)))
+            .map_err(|_| eyre!("Templates are already initialized"))
+    }
+
+    /// Compiles all templates again. If one of them fails the previous ones are kept.
+    pub fn reload() {
+        let lock = match Self::lock() {
+            Ok(lock) => lock,
+            Err(e) => return error!("Not reloading templates: {e}"),
+        };
+        match Templates::load(TEMPLATE_DIR) {
+            Ok(templates) => {
+                *lock.write().unwrap() = Arc::new(templates);
+                info!("Reloaded templates");
+            }
+            Err(e) => error!("Keeping previous templates: {e}"),
+        }
+    }
+
+    fn lock() -> Result<&'static RwLock<Arc<Templates>>> {
+        // Tests render without going through main
+        #[cfg(test)]
+        TEMPLATES.get_or_init(|| RwLock::new(Arc::new(Templates::load(TEMPLATE_DIR).unwrap())));
+        TEMPLATES
+            .get()
+            .ok_or_else(|| eyre!("Templates are used before Templates::init loaded them"))
+    }
+}
+
+fn read_sources(root: &Path, dir: &Path, sources: &mut HashMap<String, String>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).wrap_err_with(|| format!("Could not read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_sources(root, &path, sources)?;
+        } else if path.extension().is_some_and(|ext| ext == "liquid") {
+            let name = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let source = std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+            sources.insert(name, source);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use liquid::object;
+
+    #[test]
+    fn test_load_templates() {
+        let templates = Templates::load(TEMPLATE_DIR).unwrap();
+        assert!(templates.templates.contains_key("post.html.liquid"));
+        assert!(templates.templates.contains_key("navbar.liquid"));
+    }
+
+    #[test]
+    fn test_include_partial() {
+        let template = Templates::get("index.html.liquid").unwrap();
+        let html = template
+            .render(&object!({ "posts": Vec::<String>::new(), "site": crate::config::SiteConfig::get() }))
+            .unwrap();
+        // navbar.liquid is included
+        assert!(html.contains("<nav"));
+    }
+
+    #[test]
+    fn test_toc_partial() {
+        let markdown = "# Setup\n## Using `<script>`\n### Deeper\n#### Deepest\n";
+        let mut events = pulldown_cmark::Parser::new(markdown).collect::<Vec<_>>();
+        let toc =
+            crate::markdown::table_of_contents(&crate::markdown::assign_heading_ids(&mut events));
+        let html = Templates::get("toc.liquid")
+            .unwrap()
+            .render(&object!({ "post": { "toc": toc } }))
+            .unwrap();
+        assert!(html.contains(">Deepest</a>"));
+        assert_eq!(html.matches("<ul").count(), 4);
+        assert!(html.contains("Using &lt;script&gt;</a>"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_header_absolute_default_image() {
+        let mut site = crate::config::SiteConfig::get().clone();
+        site.default_image = Some("https://cdn.ducks.dev/duck.png".to_string());
+        let metadata = crate::post::PostMetadata {
+            images: Vec::new(),
+            ..Default::default()
+        };
+        let html = Templates::get("header.liquid")
+            .unwrap()
+            .render(&object!({ "metadata": metadata, "site": site }))
+            .unwrap();
+        assert!(html.contains("content=\"https://cdn.ducks.dev/duck.png\""));
+    }
+
+    #[test]
+    fn test_header_json_ld() {
+        let site = crate::config::SiteConfig::get().clone();
+        let metadata = crate::post::PostMetadata {
+            images: vec![crate::post::Image("/static/duck.png".to_string())],
+            ..Default::default()
+        };
+        let render = |is_post: bool| {
+            Templates::get("header.liquid")
+                .unwrap()
+                .render(&object!({ "metadata": metadata, "is_post": is_post, "site": site }))
+                .unwrap()
+        };
+        let post = render(true);
+        assert!(post.contains(&format!("\"{}/static/duck.png\"", site.base_url)));
+        assert!(post.contains("content=\"article\""));
+        assert!(!render(false).contains("content=\"article\""));
+    }
+}
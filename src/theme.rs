@@ -0,0 +1,96 @@
+//! Classes and attributes added to the html elements rendered from markdown, set in the
+//! `[theme]` table of duckblog.toml:
+//!
+//! ```toml
+//! [theme]
+//! a = "text-green-500"
+//! img = { class = "mx-auto", attributes = { loading = "lazy" } }
+//! ```
+use pulldown_cmark_escape::escape_html;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Styles by html element name. `code` is inline code, `code_block` the `<code>` inside a `<pre>`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Theme(HashMap<String, ElementStyle>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ElementStyle {
+    Class(String),
+    Full {
+        #[serde(default)]
+        class: String,
+        #[serde(default)]
+        attributes: BTreeMap<String, String>,
+    },
+}
+
+impl Theme {
+    /// Classes configured for `element`, if any
+    pub fn class(&self, element: &str) -> Option<&str> {
+        match self.0.get(element)? {
+            ElementStyle::Class(class) | ElementStyle::Full { class, .. } => {
+                Some(class.as_str()).filter(|class| !class.is_empty())
+            }
+        }
+    }
+
+    /// Additional attributes configured for `element`
+    pub fn extra_attributes(&self, element: &str) -> impl Iterator<Item = (&String, &String)> {
+        match self.0.get(element) {
+            Some(ElementStyle::Full { attributes, .. }) => Some(attributes.iter()),
+            _ => None,
+        }
+        .into_iter()
+        .flatten()
+    }
+
+    /// Renders the attributes of `element` as ` class="..." name="value"`, ready to be put into a tag.
+    /// `classes` are put before the configured ones.
+    pub fn attributes(&self, element: &str, classes: &[&str]) -> String {
+        let classes = classes
+            .iter()
+            .copied()
+            .chain(self.class(element))
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let mut html = String::new();
+        if !classes.is_empty() {
+            html.push_str(" class=\"");
+            escape_html(&mut html, &classes).unwrap();
+            html.push('"');
+        }
+        for (name, value) in self.extra_attributes(element) {
+            html.push(' ');
+            escape_html(&mut html, name).unwrap();
+            html.push_str("=\"");
+            escape_html(&mut html, value).unwrap();
+            html.push('"');
+        }
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes() {
+        let theme: Theme = toml::from_str(
+            r#"
+            a = "text-green-500"
+            img = { class = "mx-auto", attributes = { loading = "lazy" } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(theme.attributes("a", &[]), r#" class="text-green-500""#);
+        assert_eq!(
+            theme.attributes("img", &["wide"]),
+            r#" class="wide mx-auto" loading="lazy""#
+        );
+        assert_eq!(theme.attributes("p", &[]), "");
+    }
+}
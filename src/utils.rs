@@ -1,30 +1,56 @@
+use crate::config::SiteConfig;
 use crate::post::PostMetadata;
-use liquid::{object, Template};
-use tokio::fs::read_to_string;
-use tracing::{debug, info_span, instrument};
+use crate::templates::Templates;
+use liquid::object;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tracing::{debug, instrument};
+/// Renders `header.liquid`. Only posts get the article metadata like `og:type` and JSON-LD.
 #[instrument]
-pub(crate) async fn build_header(post: Option<PostMetadata>) -> String {
-    let template = liquid_parse("header.liquid").await;
+pub(crate) fn build_header(post: Option<PostMetadata>, is_post: bool) -> String {
+    let template = Templates::get("header.liquid").unwrap();
     let metadata = post.unwrap_or_default();
     if !metadata.images.is_empty() {
         debug!("Images: {:#?}", metadata.images);
     }
-    let globals = object!({ "metadata": metadata });
+    let globals = object!({ "metadata": metadata, "is_post": is_post, "site": SiteConfig::get() });
     template.render(&globals).unwrap()
 }
-#[instrument(skip(file))]
-pub(crate) async fn liquid_parse(file: impl ToString) -> Template {
-    let compiler = liquid::ParserBuilder::with_stdlib()
-        .build()
-        .expect("Could not build liquid compiler");
-    let file = info_span!("Reading file")
-        .in_scope(|| async move {
-            read_to_string(format!("liquid/{}", file.to_string()))
-                .await
-                .unwrap()
-        })
-        .await;
-    compiler.parse(&file).unwrap()
+
+/// Title of the feeds for all posts or only those tagged with `tag`
+pub(crate) fn feed_title(tag: Option<&str>) -> String {
+    let title = &SiteConfig::get().title;
+    match tag {
+        Some(tag) => format!("{title} - #{tag}"),
+        None => title.clone(),
+    }
+}
+
+/// Characters that can't appear unescaped in a path segment, non-ASCII is always encoded
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Path of the page of `tag` (`/tags/rust`), percent-encoded so it is a valid url
+pub(crate) fn tag_path(tag: &str) -> String {
+    format!("/tags/{}", utf8_percent_encode(tag, PATH_SEGMENT))
+}
+
+/// Absolute url of the feed `file` (`feed.xml`) for all posts or only those tagged with `tag`
+pub(crate) fn feed_url(tag: Option<&str>, file: &str) -> String {
+    let config = SiteConfig::get();
+    match tag {
+        Some(tag) => config.url(format!("{}/{file}", tag_path(tag))),
+        None => config.url(format!("/{file}")),
+    }
 }
 
 #[instrument]
@@ -33,3 +59,26 @@ pub(crate) fn get_reading_time(text: &str) -> usize {
     let words = text.split_whitespace().count();
     (words as f64 / 200.0).ceil() as usize
 }
+
+/// Turns a title into a url friendly slug: `Making Code writing Code` -> `making-code-writing-code`
+pub(crate) fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            slugify("Writing a BitBoard in Rust Pt. 1: The Basics"),
+            "writing-a-bitboard-in-rust-pt-1-the-basics"
+        );
+        assert_eq!(slugify("  Hello -- World! "), "hello-world");
+    }
+}
@@ -0,0 +1,147 @@
+//! Watches the posts, the about page and the templates and republishes posts when they change
+use crate::config::SiteConfig;
+use crate::livereload;
+use crate::post::Post;
+use crate::shortcodes::SHORTCODE_DIR;
+use crate::store::PostStore;
+use crate::templates::{Templates, TEMPLATE_DIR};
+use color_eyre::Result;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument};
+
+/// Editors save in several steps, changes this close together are handled as one batch
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Starts watching for changes. Watching stops when the returned watcher is dropped.
+#[instrument(err)]
+pub fn watch() -> Result<RecommendedWatcher> {
+    let config = SiteConfig::get();
+    let posts_dir = config.content_path("posts");
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<Event>| match result {
+            Ok(event) if is_change(&event.kind) => {
+                let _ = sender.send(event.paths);
+            }
+            Ok(_) => {}
+            Err(e) => error!("Error while watching files: {e}"),
+        })?;
+    watcher.watch(Path::new(&posts_dir), RecursiveMode::NonRecursive)?;
+    watcher.watch(
+        Path::new(&config.content_path("about.md")),
+        RecursiveMode::NonRecursive,
+    )?;
+    watcher.watch(Path::new(TEMPLATE_DIR), RecursiveMode::Recursive)?;
+
+    let dirs = WatchedDirs {
+        posts: posts_dir.clone(),
+        posts_canonical: Path::new(&posts_dir).canonicalize()?,
+        shortcodes: Path::new(TEMPLATE_DIR).join(SHORTCODE_DIR).canonicalize()?,
+    };
+    tokio::spawn(async move {
+        while let Some(mut paths) = receiver.recv().await {
+            tokio::time::sleep(DEBOUNCE).await;
+            while let Ok(more) = receiver.try_recv() {
+                paths.extend(more);
+            }
+            paths.sort();
+            paths.dedup();
+            reload(&dirs, paths).await;
+            livereload::notify();
+        }
+    });
+    Ok(watcher)
+}
+
+struct WatchedDirs {
+    /// As configured, used to name the files in the store
+    posts: String,
+    posts_canonical: PathBuf,
+    shortcodes: PathBuf,
+}
+
+/// Reloads whatever a batch of changed `paths` affects
+async fn reload(dirs: &WatchedDirs, paths: Vec<PathBuf>) {
+    let is_template = |path: &&PathBuf| path.extension().is_some_and(|ext| ext == "liquid");
+    let (templates, other): (Vec<_>, Vec<_>) = paths.iter().partition(is_template);
+    if !templates.is_empty() {
+        Templates::reload();
+    }
+    // Shortcodes are rendered into the posts when they are parsed
+    if templates
+        .iter()
+        .any(|path| parent_dir(path).is_some_and(|dir| dir.starts_with(&dirs.shortcodes)))
+    {
+        match Post::parse_all_posts().await {
+            Ok(posts) => info!("Reloaded {} posts", posts.len()),
+            Err(e) => error!("Keeping previous posts: {e}"),
+        }
+        return;
+    }
+    for path in other {
+        let in_posts_dir = parent_dir(path).is_some_and(|dir| dir == dirs.posts_canonical);
+        if in_posts_dir && path.extension().is_some_and(|ext| ext == "md") {
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            reload_post(format!("{}/{file_name}", dirs.posts)).await;
+        } else {
+            info!("{} changed", path.display());
+        }
+    }
+}
+
+/// Canonical parent, which still exists when the file itself was deleted
+fn parent_dir(path: &Path) -> Option<PathBuf> {
+    path.parent().and_then(|parent| parent.canonicalize().ok())
+}
+
+/// Whether a file was created, written, renamed or deleted. Reading a file is reported as an
+/// access event, reacting to those would make every reload trigger the next one.
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+    )
+}
+
+/// Parses `file` again and publishes a store containing the new version, or none if it was deleted
+#[instrument]
+async fn reload_post(file: String) {
+    let post = if Path::new(&file).exists() {
+        match Post::load(file.clone()).await {
+            Ok(post) => Some(post),
+            // Keep serving the old version until the post is fixed
+            Err(e) => return error!("Could not reload {file}: {e}"),
+        }
+    } else {
+        None
+    };
+    info!("Reloaded {file}");
+    PostStore::replace(PostStore::current().with_file(&file, post));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, AccessMode, CreateKind, DataChange};
+
+    #[test]
+    fn test_is_change() {
+        assert!(is_change(&EventKind::Create(CreateKind::File)));
+        assert!(is_change(&EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        // Our own reads after a reload
+        assert!(!is_change(&EventKind::Access(AccessKind::Close(
+            AccessMode::Read
+        ))));
+        assert!(!is_change(&EventKind::Access(AccessKind::Open(
+            AccessMode::Any
+        ))));
+    }
+}